@@ -0,0 +1,77 @@
+//! Bulk insertion timings. Run with `cargo bench --bench insert`.
+
+use std::hint::black_box;
+use std::time::Instant;
+
+use quadtree::{generate_points, BufferedQuadTree, QuadTree, Rect};
+
+const POINTS: usize = 100_000;
+const CAPACITY: usize = 8;
+const RUNS: u32 = 20;
+
+/// Runs `f` `RUNS` times and prints the mean time per run.
+fn bench<F: FnMut()>(name: &str, mut f: F) {
+    f();
+
+    let started = Instant::now();
+    for _ in 0..RUNS {
+        f();
+    }
+    let per_run = started.elapsed() / RUNS;
+
+    println!("{:<32} {:>10.3} ms", name, per_run.as_secs_f64() * 1000.0);
+}
+
+fn main() {
+    let boundary = Rect { x: 0, y: 0, w: 4096, h: 4096 };
+    let points = generate_points(&boundary, POINTS, 1);
+
+    // Uniform data fills a tree of about log4(POINTS / CAPACITY) levels.
+    bench("insert on demand", || {
+        let mut tree = QuadTree::new(boundary.clone(), CAPACITY);
+        for point in &points {
+            tree.insert(point.clone());
+        }
+        black_box(tree);
+    });
+
+    bench("insert presplit depth 6", || {
+        let mut tree = QuadTree::with_presplit(boundary.clone(), CAPACITY, 6);
+        for point in &points {
+            tree.insert(point.clone());
+        }
+        black_box(tree);
+    });
+
+    bench("from_points", || {
+        black_box(QuadTree::from_points(boundary.clone(), CAPACITY, &points));
+    });
+
+    // Checks each point once on insert, then rebuilds through the
+    // crate-internal path that skips the root bounds check.
+    bench("BufferedQuadTree build", || {
+        let mut tree = BufferedQuadTree::new(boundary.clone(), CAPACITY);
+        for point in &points {
+            tree.insert(point.clone());
+        }
+        black_box(tree.tree());
+    });
+
+    // shrink_root fits every item to the new root once, then rebuilds
+    // without the root bounds check. It also pays for taking the entries
+    // out of the old tree, so compare it against from_points over the same
+    // boundary as an upper bound.
+    let quarter = Rect { x: 0, y: 0, w: 2048, h: 2048 };
+    let clustered = generate_points(&quarter, POINTS, 2);
+    let loaded = QuadTree::from_points(boundary.clone(), CAPACITY, &clustered);
+
+    bench("from_points into quarter", || {
+        black_box(QuadTree::from_points(quarter.clone(), CAPACITY, &clustered));
+    });
+
+    bench("shrink_root rebuild", || {
+        let mut tree = loaded.clone();
+        tree.shrink_root();
+        black_box(tree);
+    });
+}
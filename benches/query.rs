@@ -0,0 +1,77 @@
+//! Query timings. Run with `cargo bench --bench query`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use quadtree::{generate_points, QuadTree, Rect};
+
+const RUNS: u32 = 20;
+
+/// Counts heap allocations so benches can report allocations per query.
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+/// Runs `f` `RUNS` times and prints the mean time and allocations per run.
+fn bench<F: FnMut()>(name: &str, mut f: F) {
+    f();
+
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let started = Instant::now();
+    for _ in 0..RUNS {
+        f();
+    }
+    let per_run = started.elapsed() / RUNS;
+    let allocations = (ALLOCATIONS.load(Ordering::Relaxed) - allocations) / RUNS as usize;
+
+    println!("{:<36} {:>10.3} ms {:>8} allocs", name, per_run.as_secs_f64() * 1000.0, allocations);
+}
+
+fn main() {
+    let boundary = Rect { x: 0, y: 0, w: 4096, h: 4096 };
+    let range = Rect { x: 100, y: 100, w: 1000, h: 1000 };
+
+    let empty = QuadTree::new(boundary.clone(), 8);
+    bench("100k queries, empty tree", || {
+        for _ in 0..100_000 {
+            black_box(empty.query(black_box(&range)));
+        }
+    });
+
+    // Large leaves make the per-point filter dominate, which is where the
+    // SoA layout is meant to help.
+    let points = generate_points(&boundary, 200_000, 2);
+    let ranges: Vec<Rect> = generate_points(&boundary, 1000, 3)
+        .into_iter()
+        .map(|p| Rect { x: p.x, y: p.y, w: 300, h: 300 })
+        .collect();
+
+    for soa in [false, true] {
+        let mut tree = QuadTree::new(boundary.clone(), 512).with_soa_leaves(soa);
+        for point in &points {
+            tree.insert(point.clone());
+        }
+
+        bench(&format!("1k queries, capacity 512, soa {}", soa), || {
+            for range in &ranges {
+                black_box(tree.query(range));
+            }
+        });
+    }
+}
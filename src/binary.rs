@@ -0,0 +1,312 @@
+use std::io::{self, Read, Write};
+
+use crate::{DuplicatePolicy, Entry, QuadTree, Rect, SplitStrategy, StraddlePolicy};
+
+const MAGIC: &[u8; 4] = b"QTRA";
+
+/// Deepest nesting `read_binary` accepts. Real trees stop far shorter,
+/// since every level halves the cell; deeper input is corrupt.
+const MAX_DEPTH: usize = 64;
+
+impl QuadTree {
+    /// Writes the tree's settings once, then each node's boundary, entries
+    /// and whether it has children, depth first.
+    pub fn write_binary<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(MAGIC)?;
+        self.write_config(w)?;
+        self.write_node(w)
+    }
+
+    pub fn read_binary<R: Read>(r: &mut R) -> io::Result<Self> {
+        let mut magic = [0u8; 4];
+        r.read_exact(&mut magic)?;
+
+        if &magic != MAGIC {
+            return Err(invalid("not a quadtree binary file"));
+        }
+
+        let mut tree = QuadTree::read_config(r)?;
+        tree.boundary = read_rect(r)?;
+        tree.read_node(r, 0)?;
+        tree.rebuild_insertion_order();
+        Ok(tree)
+    }
+
+    fn write_config<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(&(self.capacity as u64).to_le_bytes())?;
+        w.write_all(&self.min_cell_size.to_le_bytes())?;
+        w.write_all(&self.grid_cell_size.to_le_bytes())?;
+        w.write_all(&[self.sorted as u8])?;
+        w.write_all(&[self.soa as u8])?;
+        w.write_all(&[(self.split_strategy == SplitStrategy::Median) as u8])?;
+        w.write_all(&[(self.straddle_policy == StraddlePolicy::Duplicate) as u8])?;
+        w.write_all(&[(self.duplicate_policy == DuplicatePolicy::RejectExact) as u8])?;
+        w.write_all(&self.next_seq.to_le_bytes())?;
+        match self.max_points {
+            Some(max_points) => {
+                w.write_all(&[1])?;
+                w.write_all(&(max_points as u64).to_le_bytes())?;
+            }
+            None => w.write_all(&[0])?,
+        }
+        match self.snap_cell {
+            Some(cell) => {
+                w.write_all(&[1])?;
+                w.write_all(&cell.to_le_bytes())?;
+            }
+            None => w.write_all(&[0])?,
+        }
+        w.write_all(&[self.snap_dedup as u8])
+    }
+
+    /// Reads the settings into an empty tree whose boundary is filled in
+    /// from the root node that follows.
+    fn read_config<R: Read>(r: &mut R) -> io::Result<Self> {
+        let capacity = read_u64(r)? as usize;
+
+        let mut tree = QuadTree::new(Rect { x: 0, y: 0, w: 0, h: 0 }, capacity);
+        tree.min_cell_size = read_i32(r)?.max(1);
+        tree.grid_cell_size = read_i32(r)?.max(1);
+        tree.sorted = read_u8(r)? != 0;
+        tree.soa = read_u8(r)? != 0;
+        tree.split_strategy = match read_u8(r)? {
+            0 => SplitStrategy::Center,
+            _ => SplitStrategy::Median,
+        };
+        tree.straddle_policy = match read_u8(r)? {
+            0 => StraddlePolicy::Ancestor,
+            _ => StraddlePolicy::Duplicate,
+        };
+        tree.duplicate_policy = match read_u8(r)? {
+            0 => DuplicatePolicy::Allow,
+            _ => DuplicatePolicy::RejectExact,
+        };
+        tree.next_seq = read_u64(r)?;
+        tree.max_points = match read_u8(r)? {
+            0 => None,
+            _ => Some(read_u64(r)? as usize),
+        };
+        tree.snap_cell = match read_u8(r)? {
+            0 => None,
+            _ => Some(read_i32(r)?.max(1)),
+        };
+        tree.snap_dedup = read_u8(r)? != 0;
+
+        Ok(tree)
+    }
+
+    fn write_node<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        write_rect(w, &self.boundary)?;
+
+        w.write_all(&(self.points.len() as u32).to_le_bytes())?;
+        for i in 0..self.points.len() {
+            write_rect(w, &self.points[i])?;
+            w.write_all(&self.seqs[i].to_le_bytes())?;
+            match self.ids[i] {
+                Some(id) => {
+                    w.write_all(&[1])?;
+                    w.write_all(&id.to_le_bytes())?;
+                }
+                None => w.write_all(&[0])?,
+            }
+            match self.radii[i] {
+                Some(radius) => {
+                    w.write_all(&[1])?;
+                    w.write_all(&radius.to_le_bytes())?;
+                }
+                None => w.write_all(&[0])?,
+            }
+        }
+
+        w.write_all(&[!self.is_leaf() as u8])?;
+        for tree in self.children_iter() {
+            tree.write_node(w)?;
+        }
+
+        Ok(())
+    }
+
+    /// Fills in the entries and children of a node whose boundary has
+    /// already been read. Children must lie inside their parent and nest
+    /// at most `MAX_DEPTH` deep, so corrupt input can't recurse forever.
+    fn read_node<R: Read>(&mut self, r: &mut R, depth: usize) -> io::Result<()> {
+        let len = read_u32(r)? as usize;
+        for _ in 0..len {
+            let point = read_rect(r)?;
+            let seq = read_u64(r)?;
+            let id = match read_u8(r)? {
+                0 => None,
+                _ => Some(read_u64(r)?),
+            };
+            let radius = match read_u8(r)? {
+                0 => None,
+                _ => Some(read_i32(r)?),
+            };
+            if let Some(radius) = radius {
+                self.max_radius = self.max_radius.max(radius);
+            }
+            self.push_entry(Entry { point, id, seq, radius });
+        }
+
+        if read_u8(r)? == 0 {
+            return Ok(());
+        }
+
+        if depth >= MAX_DEPTH {
+            return Err(invalid("quadtree nodes nest too deep"));
+        }
+
+        let mut children = Vec::with_capacity(4);
+        for _ in 0..4 {
+            let boundary = read_rect(r)?;
+            if !self.boundary.contains_rect(&boundary) {
+                return Err(invalid("child node lies outside its parent"));
+            }
+
+            let mut tree = self.child(boundary);
+            tree.read_node(r, depth + 1)?;
+            self.max_radius = self.max_radius.max(tree.max_radius);
+            children.push(tree);
+        }
+
+        let mut children = children.into_iter();
+        self.north_west = children.next();
+        self.north_east = children.next();
+        self.south_west = children.next();
+        self.south_east = children.next();
+        self.recount();
+
+        Ok(())
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn write_rect<W: Write>(w: &mut W, rect: &Rect) -> io::Result<()> {
+    for v in [rect.x, rect.y, rect.w, rect.h] {
+        w.write_all(&v.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+fn read_rect<R: Read>(r: &mut R) -> io::Result<Rect> {
+    Ok(Rect { x: read_i32(r)?, y: read_i32(r)?, w: read_i32(r)?, h: read_i32(r)? })
+}
+
+fn read_u8<R: Read>(r: &mut R) -> io::Result<u8> {
+    let mut buf = [0u8; 1];
+    r.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_i32<R: Read>(r: &mut R) -> io::Result<i32> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(i32::from_le_bytes(buf))
+}
+
+fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{generate_points, Point};
+
+    fn square(side: i32) -> Rect {
+        Rect { x: 0, y: 0, w: side, h: side }
+    }
+
+    fn round_trip(tree: &QuadTree) -> QuadTree {
+        let mut buf = Vec::new();
+        tree.write_binary(&mut buf).unwrap();
+        QuadTree::read_binary(&mut buf.as_slice()).unwrap()
+    }
+
+    /// Header of an empty default tree, ready for hand-written nodes.
+    fn header() -> Vec<u8> {
+        let mut buf = MAGIC.to_vec();
+        QuadTree::new(square(100), 1).write_config(&mut buf).unwrap();
+        buf
+    }
+
+    fn read_error(mut buf: &[u8]) -> io::ErrorKind {
+        match QuadTree::read_binary(&mut buf) {
+            Ok(_) => panic!("corrupt input was accepted"),
+            Err(err) => err.kind(),
+        }
+    }
+
+    fn write_empty_node(buf: &mut Vec<u8>, boundary: &Rect, has_children: bool) {
+        write_rect(buf, boundary).unwrap();
+        buf.extend_from_slice(&0u32.to_le_bytes());
+        buf.push(has_children as u8);
+    }
+
+    #[test]
+    fn round_trip_keeps_layout_and_entries() {
+        let mut tree = QuadTree::new(square(100), 2);
+        for p in generate_points(&square(100), 40, 1) {
+            tree.insert(p);
+        }
+        tree.insert_with_id(9, Rect::from(Point { x: 3, y: 3 }));
+        tree.insert_disc(50, 50, 7);
+
+        let read = round_trip(&tree);
+        assert!(read == tree);
+        assert_eq!(read.len(), tree.len());
+        assert_eq!(read.query_ids(&square(100)), vec![(9, Rect::from(Point { x: 3, y: 3 }))]);
+        assert_eq!(read.query_discs(&square(100)), vec![(50, 50, 7)]);
+        assert_eq!(read.insertion_seq(), tree.insertion_seq());
+    }
+
+    #[test]
+    fn round_trip_keeps_settings() {
+        let mut tree = QuadTree::new(square(100), 2).with_max_points(3).with_snap_to_grid(10, true);
+        for x in [11, 32, 53] {
+            tree.insert(Rect::from(Point { x, y: 0 }));
+        }
+
+        let mut read = round_trip(&tree);
+        assert!(!read.insert(Rect::from(Point { x: 49, y: 1 })));
+        assert!(read.insert(Rect::from(Point { x: 71, y: 0 })));
+        assert_eq!(read.len(), 3);
+        assert!(!read.contains_point(&Rect::from(Point { x: 10, y: 0 })));
+        assert!(read.contains_point(&Rect::from(Point { x: 70, y: 0 })));
+    }
+
+    #[test]
+    fn rejects_wrong_magic() {
+        assert_eq!(read_error(b"NOPE"), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_nodes_nested_too_deep() {
+        let mut buf = header();
+        for _ in 0..=MAX_DEPTH {
+            write_empty_node(&mut buf, &square(100), true);
+        }
+
+        assert_eq!(read_error(&buf), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_child_outside_parent() {
+        let mut buf = header();
+        write_empty_node(&mut buf, &square(100), true);
+        write_empty_node(&mut buf, &Rect { x: 200, y: 0, w: 50, h: 50 }, false);
+
+        assert_eq!(read_error(&buf), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,117 @@
+use crate::{QuadTree, Rect};
+
+/// Write-optimized wrapper: `insert` only appends to a flat buffer, and the
+/// tree is rebuilt from it the first time it is read after an insert. Suits
+/// workloads with many inserts between queries; a query right after an
+/// insert pays for the whole rebuild.
+pub struct BufferedQuadTree {
+    boundary: Rect,
+    capacity: usize,
+    points: Vec<Rect>,
+    tree: QuadTree,
+    dirty: bool,
+}
+
+impl BufferedQuadTree {
+    pub fn new(boundary: Rect, capacity: usize) -> Self {
+        BufferedQuadTree {
+            tree: QuadTree::new(boundary.clone(), capacity),
+            boundary,
+            capacity,
+            points: Vec::new(),
+            dirty: false,
+        }
+    }
+
+    /// Buffers `point` without touching the tree. Points outside the
+    /// boundary are rejected up front, as `QuadTree::insert` would.
+    pub fn insert(&mut self, point: impl Into<Rect>) -> bool {
+        let point = point.into();
+        if !self.boundary.contains(&point) {
+            return false;
+        }
+
+        self.points.push(point);
+        self.dirty = true;
+        true
+    }
+
+    pub fn query(&mut self, range: &Rect) -> Vec<Rect> {
+        self.tree().query(range)
+    }
+
+    /// The tree over every buffered point, rebuilt first if there were
+    /// inserts since the last read.
+    pub fn tree(&mut self) -> &QuadTree {
+        if self.dirty {
+            self.tree = QuadTree::from_points_unchecked(self.boundary.clone(), self.capacity, &self.points);
+            self.dirty = false;
+        }
+
+        &self.tree
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate_points;
+
+    fn square(side: i32) -> Rect {
+        Rect { x: 0, y: 0, w: side, h: side }
+    }
+
+    #[test]
+    fn rebuilds_only_when_read_after_insert() {
+        let mut buffered = BufferedQuadTree::new(square(100), 4);
+        assert!(!buffered.is_dirty() && buffered.is_empty());
+
+        let points = generate_points(&square(100), 50, 20);
+        for p in &points {
+            assert!(buffered.insert(p.clone()));
+        }
+        assert!(!buffered.insert(Rect { x: 200, y: 0, w: 1, h: 1 }));
+        assert!(buffered.is_dirty());
+        assert_eq!(buffered.len(), 50);
+
+        assert!(*buffered.tree() == QuadTree::from_points(square(100), 4, &points));
+        assert!(!buffered.is_dirty());
+
+        let range = Rect { x: 0, y: 0, w: 40, h: 40 };
+        let expected = points.iter().filter(|p| range.contains(p)).count();
+        assert_eq!(buffered.query(&range).len(), expected);
+    }
+
+    #[test]
+    fn inserts_after_a_read_mark_it_dirty_again() {
+        let mut buffered = BufferedQuadTree::new(square(100), 4);
+        let points = generate_points(&square(100), 60, 21);
+        let range = Rect { x: 20, y: 20, w: 60, h: 60 };
+
+        for (round, batch) in points.chunks(20).enumerate() {
+            for p in batch {
+                buffered.insert(p.clone());
+            }
+            assert!(buffered.is_dirty());
+
+            let so_far = &points[..20 * (round + 1)];
+            let mut found = buffered.query(&range);
+            found.sort_by_key(|p| (p.x, p.y));
+            assert_eq!(found, QuadTree::from_points(square(100), 4, so_far).query_ordered(&range));
+            assert!(!buffered.is_dirty());
+            assert_eq!(buffered.tree().len(), so_far.len());
+        }
+    }
+}
@@ -0,0 +1,156 @@
+#[cfg(feature = "visualize")]
+use raylib::prelude::{RaylibDraw, RaylibDrawHandle};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl Color {
+    pub const BLACK: Color = Color::new(0, 0, 0, 255);
+    pub const RED: Color = Color::new(230, 41, 55, 255);
+    pub const BLUE: Color = Color::new(0, 121, 241, 255);
+
+    pub const fn new(r: u8, g: u8, b: u8, a: u8) -> Color {
+        Color { r, g, b, a }
+    }
+}
+
+/// Colors used to render a tree and a selection over it. The default is
+/// the demo's original look.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DrawStyle {
+    pub node_outline: Color,
+    pub point: Color,
+    pub selected_point: Color,
+    pub selection: Color,
+}
+
+impl Default for DrawStyle {
+    fn default() -> Self {
+        DrawStyle {
+            node_outline: Color::BLACK,
+            point: Color::RED,
+            selected_point: Color::BLUE,
+            selection: Color::new(0, 255, 0, 100),
+        }
+    }
+}
+
+/// A surface `QuadTree::draw` can render onto.
+pub trait Canvas {
+    fn draw_rect_outline(&mut self, x: i32, y: i32, w: i32, h: i32, color: Color);
+    fn fill_rect(&mut self, x: i32, y: i32, w: i32, h: i32, color: Color);
+}
+
+#[cfg(feature = "visualize")]
+impl From<Color> for raylib::prelude::Color {
+    fn from(color: Color) -> Self {
+        raylib::prelude::Color::new(color.r, color.g, color.b, color.a)
+    }
+}
+
+#[cfg(feature = "visualize")]
+impl Canvas for RaylibDrawHandle<'_> {
+    fn draw_rect_outline(&mut self, x: i32, y: i32, w: i32, h: i32, color: Color) {
+        self.draw_rectangle_lines(x, y, w, h, raylib::prelude::Color::from(color));
+    }
+
+    fn fill_rect(&mut self, x: i32, y: i32, w: i32, h: i32, color: Color) {
+        self.draw_rectangle(x, y, w, h, raylib::prelude::Color::from(color));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{QuadTree, Rect};
+
+    /// Records every outline and fill it is asked to draw.
+    #[derive(Default)]
+    struct Recorder {
+        outlines: Vec<(Rect, Color)>,
+        fills: Vec<(Rect, Color)>,
+    }
+
+    impl Canvas for Recorder {
+        fn draw_rect_outline(&mut self, x: i32, y: i32, w: i32, h: i32, color: Color) {
+            self.outlines.push((Rect { x, y, w, h }, color));
+        }
+
+        fn fill_rect(&mut self, x: i32, y: i32, w: i32, h: i32, color: Color) {
+            self.fills.push((Rect { x, y, w, h }, color));
+        }
+    }
+
+    fn split_tree() -> QuadTree {
+        let mut tree = QuadTree::new(Rect { x: 0, y: 0, w: 100, h: 100 }, 1);
+        for (x, y) in [(10, 10), (40, 10), (60, 60)] {
+            tree.insert(Rect { x, y, w: 1, h: 1 });
+        }
+        tree
+    }
+
+    #[test]
+    fn leaves_only_skips_internal_nodes() {
+        let tree = split_tree();
+        let mut all = Recorder::default();
+        let mut leaves = Recorder::default();
+        tree.draw(&mut all, &DrawStyle::default());
+        tree.draw_leaves_only(&mut leaves, &DrawStyle::default());
+
+        let internal = all.outlines.len() - leaves.outlines.len();
+        assert_eq!(all.outlines.len(), tree.node_count());
+        assert_eq!(internal, 2);
+        assert!(leaves.outlines.iter().all(|(b, _)| tree.leaf_boundary(b.x + 1, b.y + 1) == Some(b)));
+    }
+
+    #[test]
+    fn draw_outlines_every_node() {
+        let tree = split_tree();
+        let mut canvas = Recorder::default();
+        tree.draw(&mut canvas, &DrawStyle::default());
+
+        let drawn: Vec<Rect> = canvas.outlines.iter().map(|(b, _)| b.clone()).collect();
+        let nodes: Vec<Rect> = tree.geometry_only().into_iter().map(|(b, _)| b).collect();
+        assert_eq!(drawn, nodes);
+    }
+
+    #[test]
+    fn draw_uses_style_outline_color() {
+        let tree = split_tree();
+        let style = DrawStyle { node_outline: Color::new(1, 2, 3, 4), ..DrawStyle::default() };
+        let mut canvas = Recorder::default();
+        tree.draw(&mut canvas, &style);
+
+        assert!(canvas.outlines.iter().all(|(_, color)| *color == Color::new(1, 2, 3, 4)));
+        assert_eq!(DrawStyle::default().node_outline, Color::BLACK);
+    }
+
+    #[test]
+    fn draw_selection_fills_points_and_selection_in_style_colors() {
+        let tree = split_tree();
+        let style = DrawStyle {
+            point: Color::new(1, 0, 0, 255),
+            selected_point: Color::new(0, 1, 0, 255),
+            selection: Color::new(0, 0, 1, 100),
+            ..DrawStyle::default()
+        };
+        let selection = Rect { x: 0, y: 0, w: 50, h: 50 };
+        let mut canvas = Recorder::default();
+        tree.draw_selection(&mut canvas, &style, &selection);
+
+        let mut fills = canvas.fills.clone();
+        assert_eq!(fills.pop(), Some((selection, style.selection)));
+        fills.sort_by_key(|(r, _)| (r.x, r.y));
+        assert_eq!(fills, vec![
+            (Rect { x: 10, y: 10, w: 1, h: 1 }, style.selected_point),
+            (Rect { x: 40, y: 10, w: 1, h: 1 }, style.selected_point),
+            (Rect { x: 60, y: 60, w: 1, h: 1 }, style.point),
+        ]);
+        assert!(canvas.outlines.is_empty());
+    }
+}
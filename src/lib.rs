@@ -0,0 +1,3960 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashSet, VecDeque};
+use std::fmt;
+use std::mem;
+use std::ops::AddAssign;
+
+mod binary;
+mod buffered;
+mod canvas;
+mod region;
+
+pub use buffered::BufferedQuadTree;
+pub use canvas::{Canvas, Color, DrawStyle};
+pub use region::{Circle, Region};
+
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub w: i32,
+    pub h: i32,
+}
+
+impl Rect {
+    pub fn from_center(cx: i32, cy: i32, half_w: i32, half_h: i32) -> Rect {
+        Rect { x: cx - half_w, y: cy - half_h, w: half_w * 2, h: half_h * 2 }
+    }
+
+    /// Returns the same area with non-negative width and height, moving the
+    /// origin to the top-left corner when `w` or `h` is negative.
+    pub fn normalized(&self) -> Rect {
+        Rect {
+            x: self.x.min(self.right()),
+            y: self.y.min(self.bottom()),
+            w: self.w.abs(),
+            h: self.h.abs(),
+        }
+    }
+
+    pub fn contains(&self, point: &Rect) -> bool {
+        self.contains_xy(point.x, point.y)
+    }
+
+    pub fn contains_xy(&self, x: i32, y: i32) -> bool {
+        x >= self.x && x <= self.right() &&
+        y >= self.y && y <= self.bottom()
+    }
+
+    /// Exclusive overlap test: rectangles that only share an edge do not
+    /// intersect. `contains` is inclusive on all edges, so point queries
+    /// prune with `touches` instead.
+    pub fn intersects(&self, range: &Rect) -> bool {
+        self.x < range.right() &&
+        self.right() > range.x &&
+        self.y < range.bottom() &&
+        self.bottom() > range.y
+    }
+
+    pub fn contains_rect(&self, other: &Rect) -> bool {
+        other.x >= self.x && other.right() <= self.right() &&
+        other.y >= self.y && other.bottom() <= self.bottom()
+    }
+
+    /// Inclusive overlap test: rectangles sharing an edge or a corner touch.
+    pub fn touches(&self, other: &Rect) -> bool {
+        self.x <= other.right() &&
+        self.right() >= other.x &&
+        self.y <= other.bottom() &&
+        self.bottom() >= other.y
+    }
+
+    /// Splits at the center into `[NW, NE, SW, SE]`. The four tile `self`
+    /// exactly; for odd sizes the east and south ones are a unit larger.
+    pub fn quadrants(&self) -> [Rect; 4] {
+        self.split_at(span_end(self.x, self.w / 2), span_end(self.y, self.h / 2))
+    }
+
+    fn right(&self) -> i32 {
+        span_end(self.x, self.w)
+    }
+
+    fn bottom(&self) -> i32 {
+        span_end(self.y, self.h)
+    }
+
+    /// East and south parts take whatever the west and north parts leave,
+    /// so they own the far edges.
+    fn split_at(&self, split_x: i32, split_y: i32) -> [Rect; 4] {
+        let west_w = split_x - self.x;
+        let north_h = split_y - self.y;
+        let east_w = self.w - west_w;
+        let south_h = self.h - north_h;
+
+        [
+            Rect { x: self.x, y: self.y, w: west_w, h: north_h },
+            Rect { x: split_x, y: self.y, w: east_w, h: north_h },
+            Rect { x: self.x, y: split_y, w: west_w, h: south_h },
+            Rect { x: split_x, y: split_y, w: east_w, h: south_h },
+        ]
+    }
+
+    fn distance_sq(&self, other: &Rect) -> i64 {
+        let dx = (self.x - other.x) as i64;
+        let dy = (self.y - other.y) as i64;
+        dx * dx + dy * dy
+    }
+
+    fn gap_sq(&self, other: &Rect) -> i64 {
+        let dx = (other.x as i64 - self.right() as i64).max(self.x as i64 - other.right() as i64).max(0);
+        let dy = (other.y as i64 - self.bottom() as i64).max(self.y as i64 - other.bottom() as i64).max(0);
+        dx * dx + dy * dy
+    }
+}
+
+/// Far edge of a span starting at `start`. With the `saturating` feature a
+/// span reaching past `i32::MAX` ends there instead of wrapping around, so
+/// rects near the top of the coordinate range still compare correctly.
+#[cfg(feature = "saturating")]
+fn span_end(start: i32, len: i32) -> i32 {
+    start.saturating_add(len)
+}
+
+#[cfg(not(feature = "saturating"))]
+fn span_end(start: i32, len: i32) -> i32 {
+    start + len
+}
+
+/// A bare position. Stored items are `Rect`s so sized rectangles can be kept
+/// too; a `Point` converts to the 1×1 `Rect` the tree has always used.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Point {
+    pub x: i32,
+    pub y: i32,
+}
+
+/// `Rect` used as a region or node boundary rather than a stored item.
+pub type Aabb = Rect;
+
+impl From<Point> for Rect {
+    fn from(point: Point) -> Rect {
+        Rect { x: point.x, y: point.y, w: 1, h: 1 }
+    }
+}
+
+impl From<&Rect> for Point {
+    fn from(rect: &Rect) -> Point {
+        Point { x: rect.x, y: rect.y }
+    }
+}
+
+impl From<Rect> for Point {
+    fn from(rect: Rect) -> Point {
+        Point::from(&rect)
+    }
+}
+
+impl fmt::Display for Rect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "({}, {}, {}×{})", self.x, self.y, self.w, self.h)
+    }
+}
+
+pub fn query_points(boundary: Rect, capacity: usize, points: &[Rect], range: &Rect) -> Vec<Rect> {
+    QuadTree::from_points(boundary, capacity, points)
+        .query(range)
+}
+
+/// Deterministic pseudo-random 1×1 points inside `boundary` from a
+/// 64-bit LCG, so benchmarks and demos can reproduce the same data.
+pub fn generate_points(boundary: &Rect, n: usize, seed: u64) -> Vec<Rect> {
+    let mut state = seed;
+    let mut next = |span: i32| {
+        state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        ((state >> 33) % span.max(1) as u64) as i32
+    };
+
+    (0..n)
+        .map(|_| {
+            let x = boundary.x + next(boundary.w);
+            let y = boundary.y + next(boundary.h);
+            Rect::from(Point { x, y })
+        })
+        .collect()
+}
+
+const AUTO_CAPACITY_CELL: f64 = 16.0;
+
+/// Z-order code of `(x, y)`: the bits of `x` and `y` interleaved, x lowest.
+fn morton(x: u32, y: u32) -> u64 {
+    let spread = |v: u32| {
+        let mut v = v as u64;
+        v = (v | (v << 16)) & 0x0000_ffff_0000_ffff;
+        v = (v | (v << 8)) & 0x00ff_00ff_00ff_00ff;
+        v = (v | (v << 4)) & 0x0f0f_0f0f_0f0f_0f0f;
+        v = (v | (v << 2)) & 0x3333_3333_3333_3333;
+        (v | (v << 1)) & 0x5555_5555_5555_5555
+    };
+
+    spread(x) | (spread(y) << 1)
+}
+
+/// Returned by `checked_query` when the range lies entirely outside the
+/// tree's boundary.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OutOfBounds {
+    pub range: Rect,
+    pub boundary: Rect,
+}
+
+impl fmt::Display for OutOfBounds {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "range {} lies outside boundary {}", self.range, self.boundary)
+    }
+}
+
+impl std::error::Error for OutOfBounds {}
+
+/// Where `insert_rect` keeps a rectangle that straddles child boundaries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StraddlePolicy {
+    /// Keep it at the deepest node that fully contains it.
+    Ancestor,
+    /// Store a copy in every child it overlaps. Each copy counts towards
+    /// `len`, and plain `query` may return it more than once.
+    Duplicate,
+}
+
+/// Whether inserting a rect identical to one already stored is allowed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    Allow,
+    /// Inserts of an identical `Rect` return false.
+    RejectExact,
+}
+
+/// Where `subdivide` places the lines between a node's four children.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SplitStrategy {
+    /// Split at the geometric center of the node.
+    Center,
+    /// Split at the median x and median y of the node's points.
+    Median,
+}
+
+/// A mutation recorded by a tree built `with_recording`, named after the
+/// method that made it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Op {
+    Insert(Rect),
+    InsertWithId(u64, Rect),
+    InsertDisc(i32, i32, i32),
+    InsertRect(Rect),
+    Remove(Rect),
+    RemoveById(u64),
+    Move(Rect, Rect),
+    Clear,
+    Reset(Rect, usize),
+    PruneEmpty,
+    Rebalance,
+    ShrinkRoot,
+}
+
+/// Work done by inserts since the last `take_counters`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct InsertCounters {
+    /// Node boundary checks made while routing a point.
+    pub contains_checks: u64,
+    /// Nodes split into four children.
+    pub subdivisions: u64,
+    /// Steps from a node down into its children.
+    pub descents: u64,
+}
+
+impl AddAssign for InsertCounters {
+    fn add_assign(&mut self, other: InsertCounters) {
+        self.contains_checks += other.contains_checks;
+        self.subdivisions += other.subdivisions;
+        self.descents += other.descents;
+    }
+}
+
+/// The ops recorded since `with_recording`, with the boundary and capacity
+/// the tree started from, which `reset` and `shrink_root` may change.
+#[derive(Clone, Debug)]
+struct Recording {
+    boundary: Rect,
+    capacity: usize,
+    ops: Vec<Op>,
+}
+
+#[derive(Clone, Debug)]
+struct Entry {
+    point: Rect,
+    id: Option<u64>,
+    seq: u64,
+    radius: Option<i32>,
+}
+
+#[derive(Clone)]
+pub struct QuadTree {
+    boundary: Rect,
+    capacity: usize,
+    min_cell_size: i32,
+    grid_cell_size: i32,
+    sorted: bool,
+    soa: bool,
+    split_strategy: SplitStrategy,
+    straddle_policy: StraddlePolicy,
+    duplicate_policy: DuplicatePolicy,
+    max_points: Option<usize>,
+    insertion_order: VecDeque<(u64, Rect)>,
+    snap_cell: Option<i32>,
+    snap_dedup: bool,
+    next_seq: u64,
+    log: Option<Recording>,
+    points: Vec<Rect>,
+    ids: Vec<Option<u64>>,
+    seqs: Vec<u64>,
+    radii: Vec<Option<i32>>,
+    xs: Vec<i32>,
+    ys: Vec<i32>,
+    max_radius: i32,
+    count: usize,
+    counters: InsertCounters,
+    north_west: Option<Box<QuadTree>>,
+    north_east: Option<Box<QuadTree>>,
+    south_west: Option<Box<QuadTree>>,
+    south_east: Option<Box<QuadTree>>,
+}
+
+impl QuadTree {
+    pub fn new(boundary: Rect, capacity: usize) -> Self {
+        QuadTree {
+            boundary,
+            capacity,
+            min_cell_size: 1,
+            grid_cell_size: 1,
+            sorted: false,
+            soa: false,
+            split_strategy: SplitStrategy::Center,
+            straddle_policy: StraddlePolicy::Ancestor,
+            duplicate_policy: DuplicatePolicy::Allow,
+            max_points: None,
+            insertion_order: VecDeque::new(),
+            snap_cell: None,
+            snap_dedup: false,
+            next_seq: 0,
+            log: None,
+            points: Vec::new(),
+            ids: Vec::new(),
+            seqs: Vec::new(),
+            radii: Vec::new(),
+            xs: Vec::new(),
+            ys: Vec::new(),
+            max_radius: 0,
+            count: 0,
+            counters: InsertCounters::default(),
+            north_west: None,
+            north_east: None,
+            south_west: None,
+            south_east: None,
+        }
+    }
+
+    /// Suggests a capacity for `point_count` points spread over `boundary`.
+    ///
+    /// The tree is aimed at leaves no smaller than `AUTO_CAPACITY_CELL`
+    /// units across: that fixes a target depth `d`, and the capacity is the
+    /// number of points each of the `4^d` leaves would hold on average,
+    /// clamped to `4..=64`.
+    pub fn auto_capacity(point_count: usize, boundary: &Rect) -> usize {
+        let side = boundary.w.min(boundary.h).max(1) as f64;
+        let depth = (side / AUTO_CAPACITY_CELL).log2().floor().max(0.0);
+        let leaves = 4f64.powf(depth);
+
+        ((point_count as f64 / leaves).ceil() as usize).clamp(4, 64)
+    }
+
+    /// Bulk-loads `points`. Points outside `boundary` are skipped, as
+    /// `insert` would reject them.
+    pub fn from_points(boundary: Rect, capacity: usize, points: &[Rect]) -> Self {
+        let mut tree = QuadTree::new(boundary, capacity);
+
+        for point in points {
+            tree.insert(point.clone());
+        }
+
+        tree
+    }
+
+    /// Like `from_points`, for internal rebuilds whose points are already
+    /// known to lie inside `boundary`: the root bounds check is skipped.
+    pub(crate) fn from_points_unchecked(boundary: Rect, capacity: usize, points: &[Rect]) -> Self {
+        let mut tree = QuadTree::new(boundary, capacity);
+
+        for point in points {
+            tree.insert_unchecked(point.clone());
+        }
+
+        tree
+    }
+
+    pub fn with_presplit(boundary: Rect, capacity: usize, depth: usize) -> Self {
+        let mut tree = QuadTree::new(boundary, capacity);
+        tree.presplit(depth);
+        tree
+    }
+
+    fn presplit(&mut self, depth: usize) {
+        if depth == 0 || !self.can_subdivide() {
+            return;
+        }
+
+        self.subdivide();
+
+        for tree in self.children_iter_mut() {
+            tree.presplit(depth - 1);
+        }
+    }
+
+    /// Stops subdividing nodes whose children would be narrower than
+    /// `min_cell_size`. Values below 1 are raised to 1, so coincident
+    /// points can't split cells forever.
+    pub fn with_min_cell_size(mut self, min_cell_size: i32) -> Self {
+        self.min_cell_size = min_cell_size.max(1);
+        self
+    }
+
+    /// Sets the side of a tile in world units for `query_cell_radius`.
+    pub fn with_grid_cell_size(mut self, grid_cell_size: i32) -> Self {
+        self.grid_cell_size = grid_cell_size.max(1);
+        self
+    }
+
+    pub fn with_split_strategy(mut self, split_strategy: SplitStrategy) -> Self {
+        self.split_strategy = split_strategy;
+        self
+    }
+
+    pub fn with_straddle_policy(mut self, straddle_policy: StraddlePolicy) -> Self {
+        self.straddle_policy = straddle_policy;
+        self
+    }
+
+    pub fn with_duplicate_policy(mut self, duplicate_policy: DuplicatePolicy) -> Self {
+        self.duplicate_policy = duplicate_policy;
+        self
+    }
+
+    pub fn with_sorted_points(mut self, sorted: bool) -> Self {
+        self.sorted = sorted;
+        self
+    }
+
+    /// Also keeps each node's x and y coordinates in separate arrays, which
+    /// `query` scans instead of the stored rects so the comparisons can
+    /// vectorize. Costs two extra `i32`s per point; the gain depends on the
+    /// target, so check `cargo bench --bench query` before relying on it.
+    pub fn with_soa_leaves(mut self, soa: bool) -> Self {
+        self.set_soa(soa);
+        self
+    }
+
+    fn set_soa(&mut self, soa: bool) {
+        self.soa = soa;
+        self.xs = if soa { self.points.iter().map(|p| p.x).collect() } else { Vec::new() };
+        self.ys = if soa { self.points.iter().map(|p| p.y).collect() } else { Vec::new() };
+
+        for tree in self.children_iter_mut() {
+            tree.set_soa(soa);
+        }
+    }
+
+    /// Caps the total number of points; inserting past the cap evicts the
+    /// least recently inserted point.
+    pub fn with_max_points(mut self, max_points: usize) -> Self {
+        self.max_points = Some(max_points);
+        self.rebuild_insertion_order();
+        self
+    }
+
+    /// Refills the root's `(seq, position)` queue that eviction pops from,
+    /// after items were stored or moved without going through it.
+    fn rebuild_insertion_order(&mut self) {
+        let mut order = Vec::new();
+        if self.max_points.is_some() {
+            self.collect_seqs(&mut HashSet::new(), &mut order);
+        }
+
+        order.sort_unstable_by_key(|&(seq, _)| seq);
+        self.insertion_order = order.into();
+    }
+
+    fn collect_seqs(&self, seen: &mut HashSet<u64>, order: &mut Vec<(u64, Rect)>) {
+        for (point, &seq) in self.points.iter().zip(&self.seqs) {
+            if seen.insert(seq) {
+                order.push((seq, point.clone()));
+            }
+        }
+
+        for tree in self.children_iter() {
+            tree.collect_seqs(seen, order);
+        }
+    }
+
+    /// Rounds the coordinates of points passed to `insert`, `insert_with_id`
+    /// and `insert_disc` to the nearest multiple of `cell`. With `dedup`, a point that snaps onto one already
+    /// stored is rejected instead of stored twice.
+    pub fn with_snap_to_grid(mut self, cell: i32, dedup: bool) -> Self {
+        self.snap_cell = Some(cell.max(1));
+        self.snap_dedup = dedup;
+        self
+    }
+
+    /// Returns `point` as `insert` would store it under the tree's grid
+    /// snapping, or unchanged when snapping is off.
+    pub fn snapped(&self, point: &Rect) -> Rect {
+        let Some(cell) = self.snap_cell else {
+            return point.clone();
+        };
+
+        let snap = |v: i32| (v + cell / 2).div_euclid(cell) * cell;
+        Rect { x: snap(point.x), y: snap(point.y), w: point.w, h: point.h }
+    }
+
+    /// Records every successful mutation from here on so the sequence can
+    /// be fed to `replay`; call it on an empty tree. Evictions under
+    /// `with_max_points` aren't recorded, since replaying with the same
+    /// setting evicts the same points. `map_points` can't be recorded, so
+    /// the tree it returns stops recording.
+    pub fn with_recording(mut self) -> Self {
+        self.log = Some(Recording {
+            boundary: self.boundary.clone(),
+            capacity: self.capacity,
+            ops: Vec::new(),
+        });
+        self
+    }
+
+    pub fn ops(&self) -> &[Op] {
+        self.log.as_ref().map_or(&[], |log| &log.ops)
+    }
+
+    /// Applies `ops` in order to an empty tree with this tree's settings,
+    /// starting from the boundary and capacity it was recording from (or
+    /// its current ones when it isn't recording). Replaying `ops()` this
+    /// way rebuilds an identical tree.
+    pub fn replay(&self, ops: &[Op]) -> QuadTree {
+        let (boundary, capacity) = match &self.log {
+            Some(log) => (log.boundary.clone(), log.capacity),
+            None => (self.boundary.clone(), self.capacity),
+        };
+
+        let mut tree = QuadTree::new(boundary, capacity);
+        tree.min_cell_size = self.min_cell_size;
+        tree.grid_cell_size = self.grid_cell_size;
+        tree.sorted = self.sorted;
+        tree.soa = self.soa;
+        tree.split_strategy = self.split_strategy;
+        tree.straddle_policy = self.straddle_policy;
+        tree.duplicate_policy = self.duplicate_policy;
+        tree.max_points = self.max_points;
+        tree.snap_cell = self.snap_cell;
+        tree.snap_dedup = self.snap_dedup;
+        if self.log.is_some() {
+            tree = tree.with_recording();
+        }
+
+        for op in ops {
+            match op {
+                Op::Insert(point) => {
+                    tree.insert(point.clone());
+                }
+                Op::InsertWithId(id, point) => {
+                    tree.insert_with_id(*id, point.clone());
+                }
+                Op::InsertDisc(cx, cy, r) => {
+                    tree.insert_disc(*cx, *cy, *r);
+                }
+                Op::InsertRect(rect) => {
+                    tree.insert_rect(rect.clone());
+                }
+                Op::Remove(point) => {
+                    tree.remove(point);
+                }
+                Op::RemoveById(id) => {
+                    tree.remove_by_id(*id);
+                }
+                Op::Move(from, to) => {
+                    tree.move_point(from, to.clone());
+                }
+                Op::Clear => tree.clear(),
+                Op::Reset(boundary, capacity) => tree.reset(boundary.clone(), *capacity),
+                Op::PruneEmpty => tree.prune_empty(),
+                Op::Rebalance => tree.rebalance(),
+                Op::ShrinkRoot => tree.shrink_root(),
+            }
+        }
+
+        tree
+    }
+
+    fn record(&mut self, op: Op) {
+        if let Some(log) = &mut self.log {
+            log.ops.push(op);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.record(Op::Clear);
+        self.clear_nodes();
+    }
+
+    fn clear_nodes(&mut self) {
+        self.counters = self.take_counters();
+        self.points.clear();
+        self.ids.clear();
+        self.seqs.clear();
+        self.radii.clear();
+        self.xs.clear();
+        self.ys.clear();
+        self.max_radius = 0;
+        self.count = 0;
+        self.insertion_order.clear();
+        self.north_west = None;
+        self.north_east = None;
+        self.south_west = None;
+        self.south_east = None;
+    }
+
+    /// Clears the tree and repurposes it for a new region, keeping its
+    /// other settings and allocations.
+    pub fn reset(&mut self, boundary: Rect, capacity: usize) {
+        self.record(Op::Reset(boundary.clone(), capacity));
+        self.clear_nodes();
+        self.boundary = boundary;
+        self.capacity = capacity;
+    }
+
+    pub fn insert(&mut self, point: impl Into<Rect>)  -> bool {
+        let point = point.into();
+        let inserted = self.insert_tracked(point.clone(), None, None);
+        if inserted {
+            self.record(Op::Insert(self.snapped(&point)));
+        }
+
+        inserted
+    }
+
+    /// Like `insert`, but reports rejected points on stderr in debug builds.
+    pub fn insert_or_log(&mut self, point: impl Into<Rect>) -> bool {
+        let point = point.into();
+        let inserted = self.insert(point.clone());
+
+        if !inserted && cfg!(debug_assertions) && !self.boundary.contains(&point) {
+            eprintln!("quadtree: rejected point {:?} outside boundary {:?}", point, self.boundary);
+        }
+
+        inserted
+    }
+
+    pub fn insert_all(&mut self, points: Vec<Rect>) -> Vec<Rect> {
+        let mut rejected = Vec::new();
+
+        for point in points {
+            if !self.insert(point.clone()) {
+                rejected.push(point);
+            }
+        }
+
+        rejected
+    }
+
+    pub fn insert_with_id(&mut self, id: u64, point: Rect) -> bool {
+        let inserted = self.insert_tracked(point.clone(), Some(id), None);
+        if inserted {
+            self.record(Op::InsertWithId(id, self.snapped(&point)));
+        }
+
+        inserted
+    }
+
+    /// Stores a disc by its center; `query_discs` also finds discs whose
+    /// center lies outside the range but whose radius reaches into it.
+    pub fn insert_disc(&mut self, cx: i32, cy: i32, r: i32) -> bool {
+        let inserted = self.insert_tracked(Rect::from(Point { x: cx, y: cy }), None, Some(r));
+        if inserted {
+            self.record(Op::InsertDisc(cx, cy, r));
+        }
+
+        inserted
+    }
+
+    /// Stores a sized rectangle by its whole area rather than its top-left
+    /// corner, placing it according to the tree's `StraddlePolicy`. Use
+    /// `query_overlaps` to find rectangles stored this way.
+    pub fn insert_rect(&mut self, rect: Rect) -> bool {
+        if !self.boundary.contains_rect(&rect) || self.is_rejected_duplicate(&rect) {
+            return false;
+        }
+
+        let entry = Entry { point: rect.clone(), id: None, seq: self.next_seq, radius: None };
+        let inserted = self.insert_area_entry(entry);
+        if inserted {
+            self.record(Op::InsertRect(rect.clone()));
+        }
+
+        self.finish_insert(inserted, rect)
+    }
+
+    /// Inserts a point already known to lie inside the boundary, skipping
+    /// the root bounds check. Children still check while routing.
+    fn insert_unchecked(&mut self, point: Rect) {
+        debug_assert!(self.boundary.contains(&point), "{:?} outside {:?}", point, self.boundary);
+
+        let entry = Entry { point: point.clone(), id: None, seq: self.next_seq, radius: None };
+        let inserted = self.route_entry(entry);
+        self.finish_insert(inserted, point);
+    }
+
+    fn insert_tracked(&mut self, point: Rect, id: Option<u64>, radius: Option<i32>) -> bool {
+        let point = self.snapped(&point);
+        if (self.snap_dedup && self.contains_point(&point)) || self.is_rejected_duplicate(&point) {
+            return false;
+        }
+
+        let entry = Entry { point: point.clone(), id, seq: self.next_seq, radius };
+        let inserted = self.insert_entry(entry);
+        self.finish_insert(inserted, point)
+    }
+
+    fn is_rejected_duplicate(&self, point: &Rect) -> bool {
+        self.duplicate_policy == DuplicatePolicy::RejectExact && self.contains_point(point)
+    }
+
+    fn finish_insert(&mut self, inserted: bool, point: Rect) -> bool {
+        if !inserted {
+            return false;
+        }
+
+        let seq = self.next_seq;
+        self.next_seq += 1;
+
+        if let Some(max_points) = self.max_points {
+            self.insertion_order.push_back((seq, point));
+            while self.len() > max_points && self.evict_oldest() {}
+
+            // Removals leave stale entries behind; drop them once they
+            // outnumber the live ones.
+            if self.insertion_order.len() > 2 * self.len() + 64 {
+                self.rebuild_insertion_order();
+            }
+        }
+
+        true
+    }
+
+    /// Removes the least recently inserted item still stored, popping
+    /// entries for items that were removed since.
+    fn evict_oldest(&mut self) -> bool {
+        while let Some((seq, point)) = self.insertion_order.pop_front() {
+            if self.remove_seq_at(seq, &point) {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Removes every entry with `seq`, descending only into nodes that
+    /// `point` touches, which covers each copy `StraddlePolicy::Duplicate`
+    /// stored.
+    fn remove_seq_at(&mut self, seq: u64, point: &Rect) -> bool {
+        if !self.boundary.touches(point) {
+            return false;
+        }
+
+        let mut removed = false;
+        while let Some(i) = self.seqs.iter().position(|&x| x == seq) {
+            self.remove_at(i);
+            removed = true;
+        }
+
+        for tree in self.children_iter_mut() {
+            removed |= tree.remove_seq_at(seq, point);
+        }
+        if removed {
+            self.recount();
+        }
+
+        removed
+    }
+
+    fn insert_area_entry(&mut self, entry: Entry) -> bool {
+        if self.is_leaf() {
+            if self.points.len() < self.capacity || !self.can_subdivide() {
+                self.push_entry(entry);
+                return true;
+            }
+
+            self.subdivide();
+        }
+
+        self.counters.descents += 1;
+        let rect = &entry.point;
+        let targets: Vec<usize> = match self.straddle_policy {
+            StraddlePolicy::Ancestor => self.children_iter()
+                .position(|tree| tree.boundary.contains_rect(rect))
+                .into_iter()
+                .collect(),
+            StraddlePolicy::Duplicate => self.children_iter()
+                .enumerate()
+                .filter(|(_, tree)| tree.boundary.intersects(rect))
+                .map(|(i, _)| i)
+                .collect(),
+        };
+
+        if targets.is_empty() {
+            self.push_entry(entry);
+            return true;
+        }
+
+        for (i, tree) in self.children_iter_mut().enumerate() {
+            if targets.contains(&i) {
+                tree.insert_area_entry(entry.clone());
+            }
+        }
+        self.recount();
+
+        true
+    }
+
+    fn insert_entry(&mut self, entry: Entry) -> bool {
+        self.counters.contains_checks += 1;
+        if !self.boundary.contains_xy(entry.point.x, entry.point.y) {
+            return false;
+        }
+
+        self.route_entry(entry)
+    }
+
+    fn route_entry(&mut self, entry: Entry) -> bool {
+        if let Some(radius) = entry.radius {
+            self.max_radius = self.max_radius.max(radius);
+        }
+
+        if self.north_west.is_none() && (self.points.len() < self.capacity || !self.can_subdivide()) {
+            self.push_entry(entry);
+            return true;
+        }
+
+        if self.north_west.is_none() {
+            self.subdivide();
+        }
+
+        self.counters.descents += 1;
+        let inserted = self.children_iter_mut().any(|tree| tree.insert_entry(entry.clone()));
+        if inserted {
+            self.recount();
+        }
+
+        inserted
+    }
+
+    fn push_entry(&mut self, entry: Entry) {
+        let i = if self.sorted {
+            self.points.partition_point(|p| (p.x, p.y) <= (entry.point.x, entry.point.y))
+        } else {
+            self.points.len()
+        };
+
+        if self.soa {
+            self.xs.insert(i, entry.point.x);
+            self.ys.insert(i, entry.point.y);
+        }
+        self.points.insert(i, entry.point);
+        self.ids.insert(i, entry.id);
+        self.seqs.insert(i, entry.seq);
+        self.radii.insert(i, entry.radius);
+        self.count += 1;
+    }
+
+    fn remove_at(&mut self, i: usize) -> Entry {
+        self.count -= 1;
+        if self.soa {
+            self.xs.remove(i);
+            self.ys.remove(i);
+        }
+        Entry {
+            point: self.points.remove(i),
+            id: self.ids.remove(i),
+            seq: self.seqs.remove(i),
+            radius: self.radii.remove(i),
+        }
+    }
+
+    fn subdivide(&mut self) {
+        self.counters.subdivisions += 1;
+        let quadrants = match self.split_strategy {
+            SplitStrategy::Center => self.boundary.quadrants(),
+            SplitStrategy::Median => {
+                let (split_x, split_y) = self.median_split(self.points.iter());
+                self.boundary.split_at(split_x, split_y)
+            }
+        };
+
+        self.set_children(quadrants);
+        self.redistribute();
+        self.debug_check_invariants();
+    }
+
+    /// Panics in debug builds when the subtree's bookkeeping is off: a
+    /// stale cached `count`, per-point vecs out of step, or a leaf over
+    /// capacity that could still have been split. Does nothing in release.
+    fn debug_check_invariants(&self) {
+        if !cfg!(debug_assertions) {
+            return;
+        }
+
+        let n = self.points.len();
+        debug_assert!(
+            self.ids.len() == n && self.seqs.len() == n && self.radii.len() == n,
+            "node {:?} has per-point vecs out of step", self.boundary
+        );
+        debug_assert!(
+            !self.soa || (self.xs.len() == n && self.ys.len() == n),
+            "node {:?} has coordinate arrays out of step", self.boundary
+        );
+        debug_assert!(
+            !self.is_leaf() || n <= self.capacity || !self.can_subdivide(),
+            "leaf {:?} holds {} points, over capacity {}", self.boundary, n, self.capacity
+        );
+
+        let children: usize = self.children_iter().map(|tree| tree.count).sum();
+        debug_assert!(
+            self.count == n + children,
+            "node {:?} caches count {} but holds {}", self.boundary, self.count, n + children
+        );
+
+        for tree in self.children_iter() {
+            tree.debug_check_invariants();
+        }
+    }
+
+    /// Moves this node's entries into the new children. Points follow the
+    /// same routing as `insert`; larger rects follow the `StraddlePolicy`,
+    /// either moving into a child that fully contains them or being copied
+    /// into every child they overlap, and otherwise stay here.
+    fn redistribute(&mut self) {
+        for entry in self.drain_entries() {
+            let rect = &entry.point;
+
+            let moved = if rect.w <= 1 && rect.h <= 1 {
+                self.children_iter_mut().any(|tree| tree.insert_entry(entry.clone()))
+            } else {
+                match self.straddle_policy {
+                    StraddlePolicy::Ancestor => {
+                        match self.children_iter_mut().find(|tree| tree.boundary.contains_rect(rect)) {
+                            Some(tree) => tree.insert_area_entry(entry.clone()),
+                            None => false,
+                        }
+                    }
+                    StraddlePolicy::Duplicate => {
+                        let mut moved = false;
+                        for tree in self.children_iter_mut().filter(|tree| tree.boundary.intersects(rect)) {
+                            moved |= tree.insert_area_entry(entry.clone());
+                        }
+                        moved
+                    }
+                }
+            };
+
+            if !moved {
+                self.push_entry(entry);
+            }
+        }
+        self.recount();
+    }
+
+    fn drain_entries(&mut self) -> Vec<Entry> {
+        self.count -= self.points.len();
+        self.xs.clear();
+        self.ys.clear();
+        self.points.drain(..)
+            .zip(self.ids.drain(..))
+            .zip(self.seqs.drain(..))
+            .zip(self.radii.drain(..))
+            .map(|(((point, id), seq), radius)| Entry { point, id, seq, radius })
+            .collect()
+    }
+
+    fn median_split<'a>(&self, points: impl Iterator<Item = &'a Rect>) -> (i32, i32) {
+        let b = &self.boundary;
+        let (mut xs, mut ys): (Vec<i32>, Vec<i32>) = points.map(|p| (p.x, p.y)).unzip();
+
+        if xs.is_empty() {
+            return (span_end(b.x, b.w / 2), span_end(b.y, b.h / 2));
+        }
+
+        let mid = xs.len() / 2;
+        let split_x = *xs.select_nth_unstable(mid).1;
+        let split_y = *ys.select_nth_unstable(mid).1;
+
+        (
+            split_x.clamp(span_end(b.x, self.min_cell_size), b.right() - self.min_cell_size),
+            split_y.clamp(span_end(b.y, self.min_cell_size), b.bottom() - self.min_cell_size),
+        )
+    }
+
+    fn set_children(&mut self, [nw, ne, sw, se]: [Rect; 4]) {
+        self.north_west = Some(self.child(nw));
+        self.north_east = Some(self.child(ne));
+        self.south_west = Some(self.child(sw));
+        self.south_east = Some(self.child(se));
+    }
+
+    fn can_subdivide(&self) -> bool {
+        self.boundary.w / 2 >= self.min_cell_size && self.boundary.h / 2 >= self.min_cell_size
+    }
+
+    fn child(&self, boundary: Rect) -> Box<QuadTree> {
+        let mut tree = QuadTree::new(boundary, self.capacity);
+        tree.min_cell_size = self.min_cell_size;
+        tree.sorted = self.sorted;
+        tree.soa = self.soa;
+        tree.split_strategy = self.split_strategy;
+        tree.straddle_policy = self.straddle_policy;
+        Box::new(tree)
+    }
+
+    /// Returns the points inside `range`, or an empty vec when there are
+    /// none, whether or not `range` overlaps the boundary (`checked_query`
+    /// tells the two apart). Empty subtrees are skipped without being
+    /// visited, so querying an empty tree doesn't allocate. The order
+    /// depends on the tree layout and is unspecified; use `query_ordered`
+    /// when a stable order is needed.
+    pub fn query(&self, range: &Rect) -> Vec<Rect> {
+        let mut points = Vec::new();
+        self.query_into(range, &mut points);
+        points
+    }
+
+    fn query_into(&self, range: &Rect, points: &mut Vec<Rect>) {
+        if self.count == 0 || !self.boundary.touches(range) {
+            return;
+        }
+
+        if self.within(range) {
+            self.collect_all(points);
+            return;
+        }
+
+        if self.soa {
+            self.filter_soa(range, points);
+        } else {
+            for point in &self.points {
+                if range.contains_xy(point.x, point.y) {
+                    points.push(point.clone());
+                }
+            }
+        }
+
+        for tree in self.children_iter() {
+            tree.query_into(range, points);
+        }
+    }
+
+    /// Tests the coordinate arrays 64 at a time without short-circuiting,
+    /// so the comparisons vectorize, then copies out the matching rects.
+    fn filter_soa(&self, range: &Rect, points: &mut Vec<Rect>) {
+        let (x0, x1) = (range.x, range.right());
+        let (y0, y1) = (range.y, range.bottom());
+        let mut hits = [false; 64];
+
+        for (chunk, (xs, ys)) in self.xs.chunks(64).zip(self.ys.chunks(64)).enumerate() {
+            for ((hit, &x), &y) in hits.iter_mut().zip(xs).zip(ys) {
+                *hit = (x >= x0) & (x <= x1) & (y >= y0) & (y <= y1);
+            }
+
+            for (j, _) in hits[..xs.len()].iter().enumerate().filter(|(_, &hit)| hit) {
+                points.push(self.points[chunk * 64 + j].clone());
+            }
+        }
+    }
+
+    /// Calls `f` on every point inside `range` without cloning or
+    /// collecting anything.
+    pub fn query_visit<F: FnMut(&Rect)>(&self, range: &Rect, mut f: F) {
+        self.query_visit_with(range, &mut f);
+    }
+
+    fn query_visit_with<F: FnMut(&Rect)>(&self, range: &Rect, f: &mut F) {
+        if self.count == 0 || !self.boundary.touches(range) {
+            return;
+        }
+
+        for point in &self.points {
+            if range.contains_xy(point.x, point.y) {
+                f(point);
+            }
+        }
+
+        for tree in self.children_iter() {
+            tree.query_visit_with(range, f);
+        }
+    }
+
+    pub fn count_in_range(&self, range: &Rect) -> usize {
+        let mut count = 0;
+        self.query_visit(range, |_| count += 1);
+        count
+    }
+
+    /// Lazy counterpart of `query`: nodes are visited only as results are
+    /// pulled, so `take`, `find` and friends stop the traversal early.
+    pub fn query_iter<'a>(&'a self, range: &'a Rect) -> impl Iterator<Item = &'a Rect> + 'a {
+        QueryIter { range, stack: vec![self], current: [].iter() }
+    }
+
+    /// Queries the square block of tiles within `cell_radius` tiles of
+    /// tile `(cell_x, cell_y)`, using the tree's grid cell size.
+    pub fn query_cell_radius(&self, cell_x: i32, cell_y: i32, cell_radius: i32) -> Vec<Rect> {
+        let cell = self.grid_cell_size;
+        let side = (2 * cell_radius + 1) * cell;
+
+        self.query(&Rect {
+            x: (cell_x - cell_radius) * cell,
+            y: (cell_y - cell_radius) * cell,
+            w: side - 1,
+            h: side - 1,
+        })
+    }
+
+    /// Like `query`, sorted by the Z-order code of each point's offset from
+    /// the boundary origin, so spatially close results sit close together.
+    pub fn query_morton_ordered(&self, range: &Rect) -> Vec<Rect> {
+        let (x0, y0) = (self.boundary.x, self.boundary.y);
+        let mut points = self.query(range);
+
+        points.sort_by_cached_key(|p| morton((p.x - x0).max(0) as u32, (p.y - y0).max(0) as u32));
+        points
+    }
+
+    /// Serializes the result of `query` as a GeoJSON `FeatureCollection`
+    /// of `Point` features, with each rect's `x`/`y` as the coordinates.
+    pub fn query_geojson(&self, range: &Rect) -> String {
+        let features: Vec<String> = self
+            .query(range)
+            .iter()
+            .map(|p| {
+                format!(
+                    r#"{{"type":"Feature","geometry":{{"type":"Point","coordinates":[{},{}]}},"properties":{{}}}}"#,
+                    p.x, p.y
+                )
+            })
+            .collect();
+
+        format!(r#"{{"type":"FeatureCollection","features":[{}]}}"#, features.join(","))
+    }
+
+    /// Like `query`, but tells a range that misses the boundary entirely
+    /// apart from one that overlaps it and simply finds no points.
+    pub fn checked_query(&self, range: &Rect) -> Result<Vec<Rect>, OutOfBounds> {
+        if !self.boundary.touches(range) {
+            return Err(OutOfBounds { range: range.clone(), boundary: self.boundary.clone() });
+        }
+
+        Ok(self.query(range))
+    }
+
+    /// Runs `query` for every range in a single traversal, so nodes near
+    /// the root are visited once rather than once per range.
+    pub fn query_many(&self, ranges: &[Rect]) -> Vec<Vec<Rect>> {
+        let mut results = vec![Vec::new(); ranges.len()];
+        if self.count == 0 {
+            return results;
+        }
+
+        let active: Vec<usize> = (0..ranges.len()).collect();
+        self.query_many_into(ranges, &active, &mut results);
+        results
+    }
+
+    fn query_many_into(&self, ranges: &[Rect], active: &[usize], results: &mut [Vec<Rect>]) {
+        if self.count == 0 {
+            return;
+        }
+
+        let mut descend = Vec::with_capacity(active.len());
+
+        for &i in active {
+            let range = &ranges[i];
+            if !self.boundary.touches(range) {
+                continue;
+            }
+
+            if self.within(range) {
+                self.collect_all(&mut results[i]);
+                continue;
+            }
+
+            for point in &self.points {
+                if range.contains_xy(point.x, point.y) {
+                    results[i].push(point.clone());
+                }
+            }
+            descend.push(i);
+        }
+
+        if descend.is_empty() {
+            return;
+        }
+
+        for tree in self.children_iter() {
+            tree.query_many_into(ranges, &descend, results);
+        }
+    }
+
+    pub fn contains_point(&self, point: &Rect) -> bool {
+        if !self.boundary.contains_xy(point.x, point.y) {
+            return false;
+        }
+
+        self.position_of(point).is_some() || self.children_iter().any(|tree| tree.contains_point(point))
+    }
+
+    /// True when every point in `points` is stored, stopping at the first
+    /// one that isn't.
+    pub fn contains_all(&self, points: &[Rect]) -> bool {
+        points.iter().all(|point| self.contains_point(point))
+    }
+
+    pub fn remove(&mut self, point: &Rect) -> bool {
+        let removed = self.remove_item(point).is_some();
+        if removed {
+            self.record(Op::Remove(point.clone()));
+        }
+
+        removed
+    }
+
+    /// Removes `point` along with every other copy of it that
+    /// `StraddlePolicy::Duplicate` stored.
+    fn remove_item(&mut self, point: &Rect) -> Option<Entry> {
+        let entry = self.remove_entry(point)?;
+        self.remove_copies(&entry);
+        Some(entry)
+    }
+
+    fn remove_copies(&mut self, entry: &Entry) {
+        if self.straddle_policy == StraddlePolicy::Duplicate {
+            self.remove_seq_at(entry.seq, &entry.point);
+        }
+    }
+
+    fn remove_entry(&mut self, point: &Rect) -> Option<Entry> {
+        if !self.boundary.contains_xy(point.x, point.y) {
+            return None;
+        }
+
+        if let Some(i) = self.position_of(point) {
+            return Some(self.remove_at(i));
+        }
+
+        let removed = self.children_iter_mut().find_map(|tree| tree.remove_entry(point));
+        self.recount();
+
+        removed
+    }
+
+    fn position_of(&self, point: &Rect) -> Option<usize> {
+        if !self.sorted {
+            return self.points.iter().position(|p| p == point);
+        }
+
+        let start = self.points.partition_point(|p| (p.x, p.y) < (point.x, point.y));
+        self.points[start..].iter()
+            .take_while(|p| (p.x, p.y) == (point.x, point.y))
+            .position(|p| p == point)
+            .map(|i| start + i)
+    }
+
+    /// Moves a stored point to `to`, snapped like `insert` would, keeping
+    /// its id, radius and place in insertion order. Sized rects are placed
+    /// again like `insert_rect` and must fit the boundary whole.
+    pub fn move_point(&mut self, from: &Rect, to: Rect) -> bool {
+        let to = self.snapped(&to);
+        let occupied = to != *from
+            && (self.is_rejected_duplicate(&to) || (self.snap_dedup && self.contains_point(&to)));
+
+        let fits = if to.w <= 1 && to.h <= 1 {
+            self.boundary.contains_xy(to.x, to.y)
+        } else {
+            self.boundary.contains_rect(&to)
+        };
+        if !fits || occupied {
+            return false;
+        }
+
+        let Some(mut entry) = self.remove_item(from) else {
+            return false;
+        };
+
+        let seq = entry.seq;
+        entry.point = to.clone();
+        let inserted = self.reinsert(entry);
+        if inserted {
+            self.moved_in_order(seq, &to);
+            self.record(Op::Move(from.clone(), to));
+        }
+
+        inserted
+    }
+
+    /// Points the eviction queue at a moved item's new position, so
+    /// `evict_oldest` still finds it under its original seq.
+    fn moved_in_order(&mut self, seq: u64, to: &Rect) {
+        if let Ok(i) = self.insertion_order.binary_search_by_key(&seq, |(seq, _)| *seq) {
+            self.insertion_order[i].1 = to.clone();
+        }
+    }
+
+    pub fn remove_by_id(&mut self, id: u64) -> bool {
+        let Some(entry) = self.remove_id(id) else {
+            return false;
+        };
+
+        self.remove_copies(&entry);
+        self.record(Op::RemoveById(id));
+        true
+    }
+
+    fn remove_id(&mut self, id: u64) -> Option<Entry> {
+        if let Some(i) = self.ids.iter().position(|&x| x == Some(id)) {
+            return Some(self.remove_at(i));
+        }
+
+        let removed = self.children_iter_mut().find_map(|tree| tree.remove_id(id));
+        self.recount();
+
+        removed
+    }
+
+    pub fn query_ids(&self, range: &Rect) -> Vec<(u64, Rect)> {
+        let mut found = Vec::new();
+
+        if !self.boundary.touches(range) {
+            return found;
+        }
+
+        for (point, id) in self.points.iter().zip(&self.ids) {
+            if let Some(id) = id {
+                if range.contains_xy(point.x, point.y) {
+                    found.push((*id, point.clone()));
+                }
+            }
+        }
+
+        for tree in self.children_iter() {
+            found.append(&mut tree.query_ids(range));
+        }
+
+        found
+    }
+
+    /// Like `query_ids`, but keeps only the first entry found for each id.
+    pub fn query_unique_ids(&self, range: &Rect) -> Vec<(u64, Rect)> {
+        let mut seen = HashSet::new();
+        let mut found = self.query_ids(range);
+
+        found.retain(|(id, _)| seen.insert(*id));
+        found
+    }
+
+    /// True when every corner stored under this node lies in `range`, so
+    /// queries can take the whole subtree unfiltered. Copies stored by
+    /// `StraddlePolicy::Duplicate` keep a corner that may lie outside the
+    /// node, so those trees always filter.
+    fn within(&self, range: &Rect) -> bool {
+        self.straddle_policy == StraddlePolicy::Ancestor && range.contains_rect(&self.boundary)
+    }
+
+    fn collect_all(&self, points: &mut Vec<Rect>) {
+        points.extend_from_slice(&self.points);
+
+        for tree in self.children_iter() {
+            tree.collect_all(points);
+        }
+    }
+
+    /// Point-typed form of `query`. `query` itself still returns `Rect`s
+    /// because stored items may be sized rects from `insert_rect`, whose
+    /// extent a `Point` would drop. `insert` accepts a `Point` directly.
+    pub fn query_positions(&self, range: &Aabb) -> Vec<Point> {
+        self.query(range).iter().map(Point::from).collect()
+    }
+
+    /// Number of inserts made through this tree so far. Snapshot it (e.g.
+    /// once per frame) and pass it to `query_recent` later.
+    pub fn insertion_seq(&self) -> u64 {
+        self.next_seq
+    }
+
+    /// Returns points in `range` inserted at or after the `since` snapshot
+    /// taken from `insertion_seq`.
+    pub fn query_recent(&self, range: &Rect, since: u64) -> Vec<Rect> {
+        let mut points = Vec::new();
+        self.query_recent_into(range, since, &mut points);
+        points
+    }
+
+    fn query_recent_into(&self, range: &Rect, since: u64, points: &mut Vec<Rect>) {
+        if !self.boundary.touches(range) {
+            return;
+        }
+
+        for (point, &seq) in self.points.iter().zip(&self.seqs) {
+            if seq >= since && range.contains_xy(point.x, point.y) {
+                points.push(point.clone());
+            }
+        }
+
+        for tree in self.children_iter() {
+            tree.query_recent_into(range, since, points);
+        }
+    }
+
+    /// Returns the part of `range` inside the root boundary, normalizing a
+    /// negative-size `range` first. A `range` that doesn't touch the
+    /// boundary yields a zero-sized rect at its own origin, which lies
+    /// outside the tree and so never matches a stored point.
+    pub fn clamp_to_boundary(&self, range: &Rect) -> Rect {
+        let b = &self.boundary;
+        let range = range.normalized();
+
+        if !b.touches(&range) {
+            return Rect { x: range.x, y: range.y, w: 0, h: 0 };
+        }
+
+        let x = range.x.max(b.x);
+        let y = range.y.max(b.y);
+        let right = range.right().min(b.right());
+        let bottom = range.bottom().min(b.bottom());
+
+        Rect { x, y, w: right - x, h: bottom - y }
+    }
+
+    /// Pairs each matched point with the order it was inserted in, counting
+    /// every successful insert made through this tree from zero.
+    pub fn query_indexed(&self, range: &Rect) -> Vec<(usize, Rect)> {
+        let mut found = Vec::new();
+        self.query_indexed_into(range, &mut found);
+        found
+    }
+
+    fn query_indexed_into(&self, range: &Rect, found: &mut Vec<(usize, Rect)>) {
+        if !self.boundary.touches(range) {
+            return;
+        }
+
+        for (point, &seq) in self.points.iter().zip(&self.seqs) {
+            if range.contains_xy(point.x, point.y) {
+                found.push((seq as usize, point.clone()));
+            }
+        }
+
+        for tree in self.children_iter() {
+            tree.query_indexed_into(range, found);
+        }
+    }
+
+    fn collect_unique(&self, seen: &mut HashSet<u64>, rects: &mut Vec<Rect>) {
+        for (rect, &seq) in self.points.iter().zip(&self.seqs) {
+            if seen.insert(seq) {
+                rects.push(rect.clone());
+            }
+        }
+
+        for tree in self.children_iter() {
+            tree.collect_unique(seen, rects);
+        }
+    }
+
+    /// Bins the points in `range` into a `cols` × `rows` grid of counts in
+    /// row-major order. Points on the far edges land in the last column/row.
+    pub fn density_grid(&self, range: &Rect, cols: usize, rows: usize) -> Vec<usize> {
+        let mut grid = vec![0; cols * rows];
+
+        if cols == 0 || rows == 0 {
+            return grid;
+        }
+
+        let bin = |offset: i32, extent: i32, cells: usize| -> usize {
+            if extent <= 0 {
+                return 0;
+            }
+            ((offset as i64 * cells as i64 / extent as i64) as usize).min(cells - 1)
+        };
+
+        for point in self.query(range) {
+            let col = bin(point.x - range.x, range.w, cols);
+            let row = bin(point.y - range.y, range.h, rows);
+            grid[row * cols + col] += 1;
+        }
+
+        grid
+    }
+
+    pub fn query_ordered(&self, range: &Rect) -> Vec<Rect> {
+        let mut points = self.query(range);
+        points.sort_by_key(|p| (p.x, p.y, p.w, p.h));
+        points
+    }
+
+    pub fn query_limited(&self, range: &Rect, max: usize) -> Vec<Rect> {
+        let mut points = Vec::new();
+        self.query_limited_into(range, max, &mut points);
+        points
+    }
+
+    fn query_limited_into(&self, range: &Rect, max: usize, points: &mut Vec<Rect>) -> bool {
+        if points.len() >= max {
+            return false;
+        }
+
+        if !self.boundary.touches(range) {
+            return true;
+        }
+
+        for point in &self.points {
+            if range.contains_xy(point.x, point.y) {
+                points.push(point.clone());
+                if points.len() >= max {
+                    return false;
+                }
+            }
+        }
+
+        self.children_iter().all(|tree| tree.query_limited_into(range, max, points))
+    }
+
+    /// Returns up to `max` points inside `range`, nearest to `focus` first.
+    /// Nodes and points are visited best-first by their distance to `focus`.
+    pub fn query_toward(&self, range: &Rect, focus: (i32, i32), max: usize) -> Vec<Rect> {
+        enum Candidate<'a> {
+            Node(&'a QuadTree),
+            Point(&'a Rect),
+        }
+
+        let focus = Rect { x: focus.0, y: focus.1, w: 0, h: 0 };
+        let mut points = Vec::new();
+        let mut candidates = Vec::new();
+        let mut heap = BinaryHeap::new();
+
+        if self.boundary.touches(range) {
+            heap.push((Reverse(self.boundary.gap_sq(&focus)), Reverse(candidates.len())));
+            candidates.push(Candidate::Node(self));
+        }
+
+        while let Some((_, Reverse(i))) = heap.pop() {
+            if points.len() >= max {
+                break;
+            }
+
+            match candidates[i] {
+                Candidate::Point(point) => points.push(point.clone()),
+                Candidate::Node(tree) => {
+                    for point in tree.points.iter().filter(|p| range.contains_xy(p.x, p.y)) {
+                        heap.push((Reverse(point.distance_sq(&focus)), Reverse(candidates.len())));
+                        candidates.push(Candidate::Point(point));
+                    }
+
+                    for child in tree.children_iter().filter(|c| c.boundary.touches(range)) {
+                        heap.push((Reverse(child.boundary.gap_sq(&focus)), Reverse(candidates.len())));
+                        candidates.push(Candidate::Node(child));
+                    }
+                }
+            }
+        }
+
+        points
+    }
+
+    /// Finds the stored point closest to `target` and its distance, which
+    /// is 0 for a point inside `target` and otherwise the Euclidean distance
+    /// to the nearest edge, rounded down.
+    pub fn nearest_to_rect(&self, target: &Rect) -> Option<(Rect, i32)> {
+        let mut best: Option<(&Rect, i64)> = None;
+        self.nearest_to_rect_into(target, &mut best);
+        best.map(|(point, dist_sq)| (point.clone(), (dist_sq as f64).sqrt() as i32))
+    }
+
+    fn nearest_to_rect_into<'a>(&'a self, target: &Rect, best: &mut Option<(&'a Rect, i64)>) {
+        if self.count == 0 || best.is_some_and(|(_, d)| self.boundary.gap_sq(target) >= d) {
+            return;
+        }
+
+        for point in &self.points {
+            let position = Rect { x: point.x, y: point.y, w: 0, h: 0 };
+            let dist_sq = target.gap_sq(&position);
+            if best.is_none_or(|(_, d)| dist_sq < d) {
+                *best = Some((point, dist_sq));
+            }
+        }
+
+        let mut children: Vec<&QuadTree> = self.children_iter().collect();
+        children.sort_by_key(|tree| tree.boundary.gap_sq(target));
+        for tree in children {
+            tree.nearest_to_rect_into(target, best);
+        }
+    }
+
+    /// Returns the boundary of the empty leaf closest to `(x, y)`, which is
+    /// the leaf containing it when that one is empty. Leaves are visited
+    /// best-first by their distance to the point.
+    pub fn nearest_empty_cell(&self, x: i32, y: i32) -> Option<Rect> {
+        let target = Rect { x, y, w: 0, h: 0 };
+        let mut nodes = vec![self];
+        let mut heap = BinaryHeap::new();
+        heap.push((Reverse(self.boundary.gap_sq(&target)), Reverse(0)));
+
+        while let Some((_, Reverse(i))) = heap.pop() {
+            let tree = nodes[i];
+
+            if tree.is_leaf() {
+                if tree.points.is_empty() {
+                    return Some(tree.boundary.clone());
+                }
+                continue;
+            }
+
+            for child in tree.children_iter() {
+                heap.push((Reverse(child.boundary.gap_sq(&target)), Reverse(nodes.len())));
+                nodes.push(child);
+            }
+        }
+
+        None
+    }
+
+    /// Pairs each matched point with the boundary of the node storing it.
+    /// That is always a leaf for points; only larger rects that straddle
+    /// children are kept at internal nodes.
+    pub fn query_with_cells(&self, range: &Rect) -> Vec<(Rect, Rect)> {
+        let mut found = Vec::new();
+        self.query_with_cells_into(range, &mut found);
+        found
+    }
+
+    fn query_with_cells_into(&self, range: &Rect, found: &mut Vec<(Rect, Rect)>) {
+        if !self.boundary.touches(range) {
+            return;
+        }
+
+        for point in &self.points {
+            if range.contains_xy(point.x, point.y) {
+                found.push((point.clone(), self.boundary.clone()));
+            }
+        }
+
+        for tree in self.children_iter() {
+            tree.query_with_cells_into(range, found);
+        }
+    }
+
+    pub fn query_discs(&self, range: &Rect) -> Vec<(i32, i32, i32)> {
+        let mut discs = Vec::new();
+        self.query_discs_into(range, &mut discs);
+        discs
+    }
+
+    fn query_discs_into(&self, range: &Rect, discs: &mut Vec<(i32, i32, i32)>) {
+        let b = &self.boundary;
+        let r = self.max_radius;
+        let reach = Rect {
+            x: span_end(b.x, -r),
+            y: span_end(b.y, -r),
+            w: span_end(b.w, 2 * r),
+            h: span_end(b.h, 2 * r),
+        };
+
+        if !reach.touches(range) {
+            return;
+        }
+
+        for (point, radius) in self.points.iter().zip(&self.radii) {
+            if let Some(r) = *radius {
+                if Rect::from_center(point.x, point.y, r, r).touches(range) {
+                    discs.push((point.x, point.y, r));
+                }
+            }
+        }
+
+        for tree in self.children_iter() {
+            tree.query_discs_into(range, discs);
+        }
+    }
+
+    pub fn any_in_range(&self, range: &Rect) -> bool {
+        self.query_iter(range).next().is_some()
+    }
+
+    /// Returns rectangles stored with `insert_rect` that overlap `range`,
+    /// each reported once even under `StraddlePolicy::Duplicate`.
+    pub fn query_overlaps(&self, range: &Rect) -> Vec<Rect> {
+        let mut seen = HashSet::new();
+        let mut rects = Vec::new();
+        self.query_overlaps_into(range, &mut seen, &mut rects);
+        rects
+    }
+
+    fn query_overlaps_into(&self, range: &Rect, seen: &mut HashSet<u64>, rects: &mut Vec<Rect>) {
+        if !self.boundary.touches(range) {
+            return;
+        }
+
+        for (rect, &seq) in self.points.iter().zip(&self.seqs) {
+            if rect.intersects(range) && seen.insert(seq) {
+                rects.push(rect.clone());
+            }
+        }
+
+        for tree in self.children_iter() {
+            tree.query_overlaps_into(range, seen, rects);
+        }
+    }
+
+    /// Like `query`, also returning the boundary of every node the query
+    /// visits. Empty subtrees are skipped just as `query` skips them.
+    pub fn query_with_trace(&self, range: &Rect) -> (Vec<Rect>, Vec<Rect>) {
+        let mut points = Vec::new();
+        let mut visited = Vec::new();
+        self.trace_into(range, &mut points, &mut visited);
+        (points, visited)
+    }
+
+    fn trace_into(&self, range: &Rect, points: &mut Vec<Rect>, visited: &mut Vec<Rect>) {
+        if self.count == 0 || !self.boundary.touches(range) {
+            return;
+        }
+
+        visited.push(self.boundary.clone());
+
+        for point in &self.points {
+            if range.contains_xy(point.x, point.y) {
+                points.push(point.clone());
+            }
+        }
+
+        for tree in self.children_iter() {
+            tree.trace_into(range, points, visited);
+        }
+    }
+
+    pub fn visit_nodes<F: FnMut(&Rect, usize)>(&self, mut f: F) {
+        self.visit_nodes_at(0, &mut f);
+    }
+
+    fn visit_nodes_at<F: FnMut(&Rect, usize)>(&self, depth: usize, f: &mut F) {
+        f(&self.boundary, depth);
+
+        for tree in self.children_iter() {
+            tree.visit_nodes_at(depth + 1, f);
+        }
+    }
+
+    pub fn geometry_only(&self) -> Vec<(Rect, usize)> {
+        let mut nodes = Vec::new();
+        self.visit_nodes(|boundary, depth| nodes.push((boundary.clone(), depth)));
+        nodes
+    }
+
+    pub fn iter_nodes_bfs(&self) -> impl Iterator<Item = (&Rect, usize)> {
+        let mut queue = VecDeque::from([(self, 0)]);
+
+        std::iter::from_fn(move || {
+            let (node, depth) = queue.pop_front()?;
+            queue.extend(node.children_iter().map(|tree| (tree, depth + 1)));
+            Some((&node.boundary, depth))
+        })
+    }
+
+    /// Pairs every point of `self` with every point of `other` at most
+    /// `max_dist` away. A negative `max_dist` matches nothing.
+    pub fn join<'a>(&'a self, other: &'a QuadTree, max_dist: i32) -> Vec<(&'a Rect, &'a Rect)> {
+        let mut pairs = Vec::new();
+        if max_dist < 0 {
+            return pairs;
+        }
+
+        let max_dist_sq = max_dist as i64 * max_dist as i64;
+        self.join_into(other, max_dist_sq, &mut pairs);
+        pairs
+    }
+
+    fn join_into<'a>(&'a self, other: &'a QuadTree, max_dist_sq: i64, pairs: &mut Vec<(&'a Rect, &'a Rect)>) {
+        if self.boundary.gap_sq(&other.boundary) > max_dist_sq {
+            return;
+        }
+
+        let mut near = Vec::new();
+
+        for point in &self.points {
+            other.collect_near(point, max_dist_sq, &mut near);
+            pairs.extend(near.drain(..).map(|other_point| (point, other_point)));
+        }
+
+        for tree in self.children_iter() {
+            for other_point in &other.points {
+                tree.collect_near(other_point, max_dist_sq, &mut near);
+                pairs.extend(near.drain(..).map(|point| (point, other_point)));
+            }
+
+            for other_tree in other.children_iter() {
+                tree.join_into(other_tree, max_dist_sq, pairs);
+            }
+        }
+    }
+
+    fn collect_near<'a>(&'a self, target: &Rect, max_dist_sq: i64, near: &mut Vec<&'a Rect>) {
+        if self.boundary.gap_sq(target) > max_dist_sq {
+            return;
+        }
+
+        for point in &self.points {
+            if point.distance_sq(target) <= max_dist_sq {
+                near.push(point);
+            }
+        }
+
+        for tree in self.children_iter() {
+            tree.collect_near(target, max_dist_sq, near);
+        }
+    }
+
+    pub fn fold<B, F: FnMut(B, &Rect) -> B>(&self, init: B, mut f: F) -> B {
+        self.fold_with(init, &mut f)
+    }
+
+    fn fold_with<B, F: FnMut(B, &Rect) -> B>(&self, init: B, f: &mut F) -> B {
+        let acc = self.points.iter().fold(init, &mut *f);
+        self.children_iter().fold(acc, |acc, tree| tree.fold_with(acc, f))
+    }
+
+    pub fn center_of_mass(&self) -> Option<(f32, f32)> {
+        let (sum_x, sum_y, count) = self.fold((0i64, 0i64, 0usize), |(x, y, n), point| {
+            (x + point.x as i64, y + point.y as i64, n + 1)
+        });
+
+        if count == 0 {
+            return None;
+        }
+
+        Some((sum_x as f32 / count as f32, sum_y as f32 / count as f32))
+    }
+
+    /// Counts points in the NW, NE, SW and SE quadrants around the pivot.
+    /// Points on the pivot lines count towards the east/south side.
+    pub fn quadrant_counts(&self, pivot_x: i32, pivot_y: i32) -> [usize; 4] {
+        let mut counts = [0; 4];
+        self.count_quadrants(pivot_x, pivot_y, &mut counts);
+        counts
+    }
+
+    fn count_quadrants(&self, pivot_x: i32, pivot_y: i32, counts: &mut [usize; 4]) {
+        let b = &self.boundary;
+        let west = b.right() < pivot_x;
+        let east = b.x >= pivot_x;
+        let north = b.bottom() < pivot_y;
+        let south = b.y >= pivot_y;
+
+        if (west || east) && (north || south) {
+            counts[east as usize + 2 * south as usize] += self.count;
+            return;
+        }
+
+        for point in &self.points {
+            let east = point.x >= pivot_x;
+            let south = point.y >= pivot_y;
+            counts[east as usize + 2 * south as usize] += 1;
+        }
+
+        for tree in self.children_iter() {
+            tree.count_quadrants(pivot_x, pivot_y, counts);
+        }
+    }
+
+    pub fn leaf_boundary(&self, x: i32, y: i32) -> Option<&Rect> {
+        self.leaf_at(x, y).map(|leaf| &leaf.boundary)
+    }
+
+    pub fn leaf_len(&self, x: i32, y: i32) -> usize {
+        self.leaf_at(x, y).map_or(0, |leaf| leaf.points.len())
+    }
+
+    fn leaf_at(&self, x: i32, y: i32) -> Option<&QuadTree> {
+        if !self.boundary.contains_xy(x, y) {
+            return None;
+        }
+
+        if self.is_leaf() {
+            return Some(self);
+        }
+
+        self.children_iter().find_map(|tree| tree.leaf_at(x, y))
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    /// Returns the counters summed over every node and resets them.
+    pub fn take_counters(&mut self) -> InsertCounters {
+        let mut counters = mem::take(&mut self.counters);
+        for tree in self.children_iter_mut() {
+            counters += tree.take_counters();
+        }
+        counters
+    }
+
+    /// Refreshes the cached `count` from this node's own points and its
+    /// children's cached counts after a child was modified.
+    fn recount(&mut self) {
+        self.count = self.points.len() + self.children_iter().map(|tree| tree.count).sum::<usize>();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Iterates over every stored point in no particular order. The length
+    /// is known up front, so `collect` allocates once.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter { stack: vec![self], current: [].iter(), remaining: self.count }
+    }
+
+    /// Estimates the heap and inline bytes used by every node and its
+    /// per-point storage.
+    pub fn memory_bytes(&self) -> usize {
+        let own = mem::size_of::<QuadTree>()
+            + self.points.capacity() * mem::size_of::<Rect>()
+            + self.ids.capacity() * mem::size_of::<Option<u64>>()
+            + self.seqs.capacity() * mem::size_of::<u64>()
+            + self.radii.capacity() * mem::size_of::<Option<i32>>()
+            + (self.xs.capacity() + self.ys.capacity()) * mem::size_of::<i32>()
+            + self.insertion_order.capacity() * mem::size_of::<(u64, Rect)>();
+
+        own + self.children_iter().map(|tree| tree.memory_bytes()).sum::<usize>()
+    }
+
+    pub fn depth(&self) -> usize {
+        self.children_iter().map(|tree| tree.depth() + 1).max().unwrap_or(0)
+    }
+
+    /// True when the deepest leaf is at most `max_ratio` times as deep as
+    /// the shallowest one. A lopsided tree may benefit from `rebalance`.
+    pub fn is_balanced(&self, max_ratio: f32) -> bool {
+        let (min, max) = self.leaf_depth_range();
+        max == 0 || max as f32 <= min as f32 * max_ratio
+    }
+
+    fn leaf_depth_range(&self) -> (usize, usize) {
+        if self.is_leaf() {
+            return (0, 0);
+        }
+
+        self.children_iter()
+            .map(|tree| tree.leaf_depth_range())
+            .fold((usize::MAX, 0), |(min, max), (lo, hi)| (min.min(lo + 1), max.max(hi + 1)))
+    }
+
+    /// Drops the children of every subtree holding no points. An emptied
+    /// child becomes an empty leaf rather than being removed, since a node
+    /// always has either four children or none.
+    pub fn prune_empty(&mut self) {
+        self.record(Op::PruneEmpty);
+        self.prune_empty_nodes();
+    }
+
+    fn prune_empty_nodes(&mut self) {
+        if self.count == 0 {
+            self.north_west = None;
+            self.north_east = None;
+            self.south_west = None;
+            self.south_east = None;
+            return;
+        }
+
+        for tree in self.children_iter_mut() {
+            tree.prune_empty_nodes();
+        }
+    }
+
+    /// Rebuilds the tree, splitting each overfull node at the median x and
+    /// median y of its points instead of at its geometric center.
+    pub fn rebalance(&mut self) {
+        self.record(Op::Rebalance);
+        let entries = self.take_unique_entries();
+        self.build_balanced(entries);
+    }
+
+    /// Rebuilds the tree over the same boundary with every stored item
+    /// passed through `f`, keeping ids and settings. Items that `f` moves
+    /// outside the boundary are dropped.
+    pub fn map_points<F: Fn(Rect) -> Rect>(mut self, f: F) -> QuadTree {
+        self.log = None;
+        for mut entry in self.take_unique_entries() {
+            entry.point = f(entry.point);
+            self.reinsert(entry);
+        }
+        self.rebuild_insertion_order();
+
+        self
+    }
+
+    /// Puts back an entry taken out during a rebuild, routing points like
+    /// `insert` and larger rects like `insert_rect`.
+    fn reinsert(&mut self, entry: Entry) -> bool {
+        if entry.point.w <= 1 && entry.point.h <= 1 {
+            self.insert_entry(entry)
+        } else if self.boundary.contains_rect(&entry.point) {
+            self.insert_area_entry(entry)
+        } else {
+            false
+        }
+    }
+
+    /// Like `reinsert`, for entries already known to fit the boundary:
+    /// points skip the root bounds check, as in `insert_unchecked`.
+    fn reinsert_unchecked(&mut self, entry: Entry) {
+        debug_assert!(self.boundary.contains(&entry.point), "{:?} outside {:?}", entry.point, self.boundary);
+
+        if entry.point.w <= 1 && entry.point.h <= 1 {
+            self.route_entry(entry);
+        } else {
+            self.insert_area_entry(entry);
+        }
+    }
+
+    /// Like `take_entries`, but keeps one entry per insert, dropping the
+    /// extra copies `StraddlePolicy::Duplicate` stores.
+    fn take_unique_entries(&mut self) -> Vec<Entry> {
+        let mut seen = HashSet::new();
+        let mut entries = self.take_entries();
+
+        entries.retain(|entry| seen.insert(entry.seq));
+        entries
+    }
+
+    fn take_entries(&mut self) -> Vec<Entry> {
+        let mut entries = self.drain_entries();
+        self.max_radius = 0;
+
+        let mut counters = InsertCounters::default();
+        for tree in self.children_iter_mut() {
+            entries.append(&mut tree.take_entries());
+            counters += tree.counters;
+        }
+        self.counters += counters;
+
+        self.north_west = None;
+        self.north_east = None;
+        self.south_west = None;
+        self.south_east = None;
+        self.count = 0;
+
+        entries
+    }
+
+    fn build_balanced(&mut self, entries: Vec<Entry>) {
+        self.max_radius = entries.iter().filter_map(|e| e.radius).fold(0, i32::max);
+
+        if entries.len() <= self.capacity || !self.can_subdivide() {
+            for entry in entries {
+                self.push_entry(entry);
+            }
+            return;
+        }
+
+        let (split_x, split_y) = self.median_split(entries.iter().map(|e| &e.point));
+        self.set_children(self.boundary.split_at(split_x, split_y));
+
+        let mut buckets: [Vec<Entry>; 4] = Default::default();
+        for entry in entries {
+            let rect = &entry.point;
+
+            if rect.w <= 1 && rect.h <= 1 {
+                let i = self.children_iter().position(|tree| tree.boundary.contains_xy(rect.x, rect.y)).unwrap_or(3);
+                buckets[i].push(entry);
+                continue;
+            }
+
+            let targets: Vec<usize> = match self.straddle_policy {
+                StraddlePolicy::Ancestor => self.children_iter()
+                    .position(|tree| tree.boundary.contains_rect(rect))
+                    .into_iter()
+                    .collect(),
+                StraddlePolicy::Duplicate => self.children_iter()
+                    .enumerate()
+                    .filter(|(_, tree)| tree.boundary.intersects(rect))
+                    .map(|(i, _)| i)
+                    .collect(),
+            };
+
+            match targets.split_last() {
+                Some((&last, rest)) => {
+                    for &i in rest {
+                        buckets[i].push(entry.clone());
+                    }
+                    buckets[last].push(entry);
+                }
+                None => self.push_entry(entry),
+            }
+        }
+
+        for (tree, bucket) in self.children_iter_mut().zip(buckets) {
+            tree.build_balanced(bucket);
+        }
+        self.recount();
+        self.debug_check_invariants();
+    }
+
+    /// Compares `self` (the previous state) against `other` (the current
+    /// state) and returns the points that were (added, removed).
+    pub fn diff(&self, other: &QuadTree) -> (Vec<Rect>, Vec<Rect>) {
+        let mut before = Vec::new();
+        let mut after = Vec::new();
+        self.collect_all(&mut before);
+        other.collect_all(&mut after);
+
+        let before: HashSet<Rect> = before.into_iter().collect();
+        let after: HashSet<Rect> = after.into_iter().collect();
+
+        let added = after.difference(&before).cloned().collect();
+        let removed = before.difference(&after).cloned().collect();
+        (added, removed)
+    }
+
+    pub fn densest_cells(&self, n: usize) -> Vec<(Rect, usize)> {
+        let mut cells = Vec::new();
+        self.collect_leaf_counts(&mut cells);
+
+        if n < cells.len() {
+            cells.select_nth_unstable_by_key(n, |cell| Reverse(cell.1));
+            cells.truncate(n);
+        }
+
+        cells.sort_by_key(|cell| Reverse(cell.1));
+        cells
+    }
+
+    /// Leaves holding more than `capacity` points, which only happens once
+    /// a leaf can no longer subdivide.
+    pub fn overfull_leaves(&self) -> Vec<(Rect, usize)> {
+        let mut cells = Vec::new();
+        self.collect_leaf_counts(&mut cells);
+
+        cells.retain(|cell| cell.1 > self.capacity);
+        cells
+    }
+
+    fn collect_leaf_counts(&self, cells: &mut Vec<(Rect, usize)>) {
+        if self.is_leaf() {
+            cells.push((self.boundary.clone(), self.points.len()));
+            return;
+        }
+
+        for tree in self.children_iter() {
+            tree.collect_leaf_counts(cells);
+        }
+    }
+
+    /// Returns the boundaries of nodes at exactly depth `d` (the root is at
+    /// depth 0). Leaves shallower than `d` are omitted.
+    pub fn cells_at_depth(&self, d: usize) -> Vec<Rect> {
+        if d == 0 {
+            return vec![self.boundary.clone()];
+        }
+
+        self.children_iter().flat_map(|tree| tree.cells_at_depth(d - 1)).collect()
+    }
+
+    pub fn points_bounds(&self) -> Option<Rect> {
+        let mut points = Vec::new();
+        self.collect_all(&mut points);
+
+        let first = points.first()?;
+        let (mut min_x, mut min_y, mut max_x, mut max_y) = (first.x, first.y, first.x, first.y);
+        for point in &points {
+            min_x = min_x.min(point.x);
+            min_y = min_y.min(point.y);
+            max_x = max_x.max(point.x);
+            max_y = max_y.max(point.y);
+        }
+
+        Some(Rect { x: min_x, y: min_y, w: max_x - min_x, h: max_y - min_y })
+    }
+
+    /// Items that a tree with `boundary` would not accept: points outside
+    /// it, and sized rects not fully inside it. Use before re-rooting to see
+    /// what would be dropped.
+    pub fn points_outside(&self, boundary: &Rect) -> Vec<Rect> {
+        let mut rects = Vec::new();
+        self.collect_unique(&mut HashSet::new(), &mut rects);
+
+        rects.retain(|rect| {
+            if rect.w <= 1 && rect.h <= 1 {
+                !boundary.contains(rect)
+            } else {
+                !boundary.contains_rect(rect)
+            }
+        });
+        rects
+    }
+
+    /// Convex hull of the centers of every stored item, via Andrew's
+    /// monotone chain. Vertices start at the leftmost point and wind
+    /// clockwise on screen (y down); collinear points are dropped.
+    pub fn convex_hull(&self) -> Vec<(i32, i32)> {
+        let mut rects = Vec::new();
+        self.collect_unique(&mut HashSet::new(), &mut rects);
+
+        let mut centers: Vec<(i32, i32)> = rects.iter().map(|r| (r.x + r.w / 2, r.y + r.h / 2)).collect();
+        centers.sort_unstable();
+        centers.dedup();
+
+        if centers.len() < 3 {
+            return centers;
+        }
+
+        let cross = |o: (i32, i32), a: (i32, i32), b: (i32, i32)| {
+            (a.0 - o.0) as i64 * (b.1 - o.1) as i64 - (a.1 - o.1) as i64 * (b.0 - o.0) as i64
+        };
+
+        let mut hull: Vec<(i32, i32)> = Vec::with_capacity(centers.len() * 2);
+        for pass in [centers.clone(), centers.into_iter().rev().collect()] {
+            let start = hull.len();
+            for p in pass {
+                while hull.len() >= start + 2 && cross(hull[hull.len() - 2], hull[hull.len() - 1], p) <= 0 {
+                    hull.pop();
+                }
+                hull.push(p);
+            }
+            hull.pop();
+        }
+
+        hull
+    }
+
+    /// Re-roots the tree at the smallest quadrant cell (as produced by
+    /// repeated subdivision of the current root) that still encloses every
+    /// stored item, sized rects included, then rebuilds it. Every item was
+    /// just checked against the new root, so points skip that check again.
+    pub fn shrink_root(&mut self) {
+        if self.is_empty() {
+            return;
+        }
+        self.record(Op::ShrinkRoot);
+
+        let entries = self.take_unique_entries();
+        let fits = |cell: &Rect| entries.iter().all(|entry| {
+            let rect = &entry.point;
+            if rect.w <= 1 && rect.h <= 1 {
+                cell.contains_xy(rect.x, rect.y)
+            } else {
+                cell.contains_rect(rect)
+            }
+        });
+
+        let mut boundary = self.boundary.clone();
+        while boundary.w / 2 >= self.min_cell_size && boundary.h / 2 >= self.min_cell_size {
+            match boundary.quadrants().into_iter().find(|q| fits(q)) {
+                Some(quadrant) => boundary = quadrant,
+                None => break,
+            }
+        }
+
+        self.boundary = boundary;
+        for entry in entries {
+            self.reinsert_unchecked(entry);
+        }
+    }
+
+    /// Returns the greatest number of stored rectangles covering a single
+    /// spot inside `range`. Rectangles are stored by their top-left corner,
+    /// so nodes can't be pruned by `range` and every stored rect is checked.
+    pub fn max_overlap(&self, range: &Rect) -> usize {
+        let mut rects = Vec::new();
+        self.collect_unique(&mut HashSet::new(), &mut rects);
+        rects.retain(|rect| rect.intersects(range));
+
+        // Coverage only changes where a rect starts or ends, or where the
+        // range itself starts for rects that began further left.
+        let (x0, x1) = (range.x, range.right());
+        let last = x1.saturating_sub(1).max(x0);
+        let mut xs: Vec<i32> = rects.iter()
+            .flat_map(|r| [r.x, r.right()])
+            .chain([x0])
+            .map(|x| x.clamp(x0, last))
+            .collect();
+        xs.sort_unstable();
+        xs.dedup();
+
+        let mut best = 0;
+        for &x in xs.iter().filter(|&&x| x < x1) {
+            let mut events: Vec<(i32, i32)> = rects.iter()
+                .filter(|r| r.x <= x && r.right() > x)
+                .flat_map(|r| [(r.y.max(range.y), 1), (r.bottom().min(range.bottom()), -1)])
+                .collect();
+            events.sort_unstable();
+
+            let mut active = 0;
+            for (_, delta) in events {
+                active += delta;
+                best = best.max(active as usize);
+            }
+        }
+
+        best
+    }
+
+    pub fn node_count(&self) -> usize {
+        1 + self.children_iter().map(|tree| tree.node_count()).sum::<usize>()
+    }
+
+    /// Sum of the perimeters of every node, i.e. the outline length `draw`
+    /// strokes, counting edges shared by neighbouring nodes once per node.
+    pub fn total_edge_length(&self) -> i64 {
+        let own = 2 * (self.boundary.w as i64 + self.boundary.h as i64);
+        own + self.children_iter().map(|tree| tree.total_edge_length()).sum::<i64>()
+    }
+
+    /// Average fraction of nodes `query_with_trace` visits over `ranges`.
+    pub fn pruning_ratio(&self, ranges: &[Rect]) -> f32 {
+        if ranges.is_empty() {
+            return 0.0;
+        }
+
+        let total = self.node_count() as f32;
+        let visited: usize = ranges.iter().map(|range| self.query_with_trace(range).1.len()).sum();
+
+        visited as f32 / total / ranges.len() as f32
+    }
+
+    pub fn boundary(&self) -> &Rect {
+        &self.boundary
+    }
+
+    /// Maps a world coordinate to the chunk it falls in when the plane is
+    /// tiled with copies of this tree's boundary, with the tree's own chunk
+    /// at `(0, 0)`. Returns `None` for a boundary with no area.
+    pub fn chunk_index(&self, x: i32, y: i32) -> Option<(i32, i32)> {
+        let b = &self.boundary;
+        if b.w <= 0 || b.h <= 0 {
+            return None;
+        }
+
+        Some(((x - b.x).div_euclid(b.w), (y - b.y).div_euclid(b.h)))
+    }
+
+    /// The items stored at this node itself, not in its children.
+    pub fn points(&self) -> &[Rect] {
+        &self.points
+    }
+
+    /// The `[NW, NE, SW, SE]` children of an internal node, or `None` for
+    /// a leaf.
+    pub fn children(&self) -> Option<[&QuadTree; 4]> {
+        match (&self.north_west, &self.north_east, &self.south_west, &self.south_east) {
+            (Some(nw), Some(ne), Some(sw), Some(se)) => Some([nw, ne, sw, se]),
+            _ => None,
+        }
+    }
+
+    /// Returns the deepest node whose boundary fully contains `range` and
+    /// that holds every point a query for `range` on `self` would return,
+    /// so the query can be run there instead. Falls back to `self`.
+    pub fn subtree_for(&self, range: &Rect) -> &QuadTree {
+        if self.points.iter().any(|p| range.contains_xy(p.x, p.y)) {
+            return self;
+        }
+
+        let mut touching = self.children_iter().filter(|tree| tree.boundary.touches(range));
+
+        match (touching.next(), touching.next()) {
+            (Some(tree), None) if tree.boundary.contains_rect(range) => tree.subtree_for(range),
+            _ => self,
+        }
+    }
+
+    pub fn empty_cells(&self, range: &Rect) -> Vec<Rect> {
+        let mut cells = Vec::new();
+
+        if !self.boundary.intersects(range) {
+            return cells;
+        }
+
+        if self.is_leaf() {
+            if self.points.is_empty() {
+                cells.push(self.boundary.clone());
+            }
+            return cells;
+        }
+
+        for tree in self.children_iter() {
+            cells.append(&mut tree.empty_cells(range));
+        }
+
+        cells
+    }
+
+    fn is_leaf(&self) -> bool {
+        self.north_west.is_none()
+    }
+
+    fn children_iter(&self) -> impl Iterator<Item = &QuadTree> {
+        self.north_west.iter()
+            .chain(self.north_east.iter())
+            .chain(self.south_west.iter())
+            .chain(self.south_east.iter())
+            .map(|tree| tree.as_ref())
+    }
+
+    fn children_iter_mut(&mut self) -> impl Iterator<Item = &mut QuadTree> {
+        self.north_west.iter_mut()
+            .chain(self.north_east.iter_mut())
+            .chain(self.south_west.iter_mut())
+            .chain(self.south_east.iter_mut())
+            .map(|tree| tree.as_mut())
+    }
+
+    pub fn draw<C: Canvas>(&self, d: &mut C, style: &DrawStyle) {
+        self.draw_nodes(d, style, false);
+    }
+
+    pub fn draw_leaves_only<C: Canvas>(&self, d: &mut C, style: &DrawStyle) {
+        self.draw_nodes(d, style, true);
+    }
+
+    /// Fills every stored item in `style.point`, or `style.selected_point`
+    /// when `selection` holds its corner as `query` would, then shades
+    /// `selection` itself in `style.selection`.
+    pub fn draw_selection<C: Canvas>(&self, d: &mut C, style: &DrawStyle, selection: &Rect) {
+        for rect in self.iter() {
+            let color = if selection.contains(rect) { style.selected_point } else { style.point };
+            d.fill_rect(rect.x, rect.y, rect.w, rect.h, color);
+        }
+
+        d.fill_rect(selection.x, selection.y, selection.w, selection.h, style.selection);
+    }
+
+    fn draw_nodes<C: Canvas>(&self, d: &mut C, style: &DrawStyle, leaves_only: bool) {
+        if !leaves_only || self.is_leaf() {
+            d.draw_rect_outline(self.boundary.x, self.boundary.y, self.boundary.w, self.boundary.h, style.node_outline);
+        }
+
+        for tree in self.children_iter() {
+            tree.draw_nodes(d, style, leaves_only);
+        }
+    }
+}
+
+pub struct Iter<'a> {
+    stack: Vec<&'a QuadTree>,
+    current: std::slice::Iter<'a, Rect>,
+    remaining: usize,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a Rect;
+
+    fn next(&mut self) -> Option<&'a Rect> {
+        loop {
+            if let Some(point) = self.current.next() {
+                self.remaining -= 1;
+                return Some(point);
+            }
+
+            let node = self.stack.pop()?;
+            self.current = node.points.iter();
+            self.stack.extend(node.children_iter());
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl ExactSizeIterator for Iter<'_> {}
+
+struct QueryIter<'a> {
+    range: &'a Rect,
+    stack: Vec<&'a QuadTree>,
+    current: std::slice::Iter<'a, Rect>,
+}
+
+impl<'a> Iterator for QueryIter<'a> {
+    type Item = &'a Rect;
+
+    fn next(&mut self) -> Option<&'a Rect> {
+        loop {
+            let range = self.range;
+            if let Some(point) = self.current.find(|p| range.contains_xy(p.x, p.y)) {
+                return Some(point);
+            }
+
+            let node = self.stack.pop()?;
+            if node.count == 0 || !node.boundary.touches(range) {
+                continue;
+            }
+
+            self.current = node.points.iter();
+            self.stack.extend(node.children_iter());
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a QuadTree {
+    type Item = &'a Rect;
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+/// Structural equality: same boundary and capacity, the same subdivisions,
+/// and the same points in each node, in any order. Configuration such as
+/// the split strategy is not compared.
+impl PartialEq for QuadTree {
+    fn eq(&self, other: &QuadTree) -> bool {
+        let sorted = |points: &[Rect]| {
+            let mut points = points.to_vec();
+            points.sort_by_key(|p| (p.x, p.y, p.w, p.h));
+            points
+        };
+
+        self.boundary == other.boundary
+            && self.capacity == other.capacity
+            && self.is_leaf() == other.is_leaf()
+            && sorted(&self.points) == sorted(&other.points)
+            && self.children_iter().zip(other.children_iter()).all(|(a, b)| a == b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pt(x: i32, y: i32) -> Rect {
+        Rect::from(Point { x, y })
+    }
+
+    fn square(side: i32) -> Rect {
+        Rect { x: 0, y: 0, w: side, h: side }
+    }
+
+    fn nodes(tree: &QuadTree) -> Vec<&QuadTree> {
+        let mut all = vec![tree];
+        all.extend(tree.children_iter().flat_map(nodes));
+        all
+    }
+
+    #[test]
+    fn empty_cells_of_unsplit_tree() {
+        let mut tree = QuadTree::new(square(100), 4);
+        assert_eq!(tree.empty_cells(&square(100)), vec![square(100)]);
+
+        tree.insert(pt(10, 10));
+        assert!(tree.empty_cells(&square(100)).is_empty());
+    }
+
+    #[test]
+    fn empty_cells_after_split() {
+        let mut tree = QuadTree::new(square(100), 1);
+        tree.insert(pt(10, 10));
+        tree.insert(pt(60, 10));
+
+        let sw = Rect { x: 0, y: 50, w: 50, h: 50 };
+        let se = Rect { x: 50, y: 50, w: 50, h: 50 };
+        assert_eq!(tree.empty_cells(&square(100)), vec![sw.clone(), se]);
+        assert_eq!(tree.empty_cells(&Rect { x: 0, y: 60, w: 20, h: 20 }), vec![sw]);
+        assert!(tree.empty_cells(&Rect { x: 0, y: 0, w: 20, h: 20 }).is_empty());
+    }
+
+    #[test]
+    fn ids_round_trip_through_remove_by_id() {
+        let mut tree = QuadTree::new(square(100), 2);
+        tree.insert_with_id(1, pt(5, 5));
+        tree.insert_with_id(2, pt(5, 5));
+        tree.insert(pt(6, 6));
+        tree.insert_with_id(3, pt(50, 50));
+
+        let mut ids: Vec<u64> = tree.query_ids(&square(100)).iter().map(|(id, _)| *id).collect();
+        ids.sort_unstable();
+        assert_eq!(ids, vec![1, 2, 3]);
+
+        assert!(tree.remove_by_id(1));
+        assert!(!tree.remove_by_id(1));
+        assert_eq!(tree.len(), 3);
+        assert!(tree.contains_point(&pt(5, 5)));
+        assert!(tree.query_ids(&square(100)).iter().all(|(id, _)| *id != 1));
+    }
+
+    #[test]
+    fn trace_lists_visited_nodes() {
+        let mut tree = QuadTree::new(square(100), 1);
+        for p in [pt(10, 10), pt(60, 10), pt(10, 60), pt(60, 60)] {
+            tree.insert(p);
+        }
+
+        let (points, visited) = tree.query_with_trace(&Rect { x: 0, y: 0, w: 20, h: 20 });
+        assert_eq!(points, vec![pt(10, 10)]);
+        assert_eq!(visited, vec![square(100), Rect { x: 0, y: 0, w: 50, h: 50 }]);
+    }
+
+    #[test]
+    fn bfs_yields_nodes_level_by_level() {
+        let mut tree = QuadTree::new(square(100), 1);
+        for i in 0..20 {
+            tree.insert(pt(i * 3 % 97, i * 7 % 89));
+        }
+
+        let nodes: Vec<(&Rect, usize)> = tree.iter_nodes_bfs().collect();
+        assert_eq!(nodes[0], (&square(100), 0));
+        assert_eq!(nodes[1..5].iter().map(|n| n.1).collect::<Vec<_>>(), vec![1; 4]);
+        assert!(nodes.windows(2).all(|w| w[0].1 <= w[1].1));
+        assert_eq!(nodes.len(), tree.node_count());
+    }
+
+    #[test]
+    fn min_cell_size_stops_subdivision() {
+        let mut tree = QuadTree::new(square(128), 2).with_min_cell_size(4);
+        for i in 0..200 {
+            tree.insert(pt(10 + i % 3, 10 + i % 2));
+        }
+
+        assert_eq!(tree.len(), 200);
+        assert!(tree.iter_nodes_bfs().all(|(b, _)| b.w >= 4 && b.h >= 4));
+    }
+
+    #[test]
+    fn zero_min_cell_size_handles_coincident_points() {
+        let mut tree = QuadTree::new(square(128), 2).with_min_cell_size(0);
+        for _ in 0..50 {
+            assert!(tree.insert(pt(10, 10)));
+        }
+
+        assert_eq!(tree.len(), 50);
+        assert!(tree.iter_nodes_bfs().all(|(b, _)| b.w >= 1 && b.h >= 1));
+    }
+
+    #[test]
+    fn join_matches_brute_force() {
+        let points = generate_points(&square(200), 300, 7);
+        let (left, right) = points.split_at(150);
+        let a = QuadTree::from_points(square(200), 3, left);
+        let b = QuadTree::from_points(square(200), 2, right);
+
+        let pairs = a.join(&b, 10);
+        let expected = left.iter()
+            .flat_map(|p| right.iter().map(move |q| (p, q)))
+            .filter(|(p, q)| p.distance_sq(q) <= 100)
+            .count();
+
+        assert_eq!(pairs.len(), expected);
+        assert!(pairs.iter().all(|(p, q)| p.distance_sq(q) <= 100));
+    }
+
+    #[test]
+    fn join_with_negative_distance_is_empty() {
+        let a = QuadTree::from_points(square(10), 4, &[pt(1, 1)]);
+        let b = QuadTree::from_points(square(10), 4, &[pt(1, 1)]);
+
+        assert_eq!(a.join(&b, 0).len(), 1);
+        assert!(a.join(&b, -1).is_empty());
+    }
+
+    #[test]
+    fn invariants_hold_after_inserts_and_removes() {
+        let points = generate_points(&square(256), 500, 3);
+        let mut tree = QuadTree::from_points(square(256), 4, &points).with_soa_leaves(true);
+        for p in &points[..250] {
+            tree.remove(p);
+        }
+
+        tree.debug_check_invariants();
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "caches count")]
+    fn invariants_catch_stale_count() {
+        let mut tree = QuadTree::from_points(square(100), 1, &[pt(10, 10), pt(60, 60)]);
+        tree.count += 1;
+
+        tree.debug_check_invariants();
+    }
+
+    #[test]
+    fn sorted_leaves_find_and_remove_points() {
+        let points = generate_points(&square(100), 120, 3);
+        let mut tree = QuadTree::new(square(100), 50).with_sorted_points(true);
+        for p in &points {
+            tree.insert(p.clone());
+        }
+
+        for node in nodes(&tree) {
+            assert!(node.points().windows(2).all(|w| (w[0].x, w[0].y) <= (w[1].x, w[1].y)));
+        }
+        assert!(points.iter().all(|p| tree.contains_point(p)));
+
+        for p in &points[..60] {
+            assert!(tree.remove(p));
+        }
+        assert_eq!(tree.len(), 60);
+        assert!(points[60..].iter().all(|p| tree.contains_point(p)));
+    }
+
+    #[test]
+    fn from_center_spans_both_sides() {
+        let rect = Rect::from_center(50, 40, 10, 5);
+        assert_eq!(rect, Rect { x: 40, y: 35, w: 20, h: 10 });
+        assert!(rect.contains(&pt(40, 35)) && rect.contains(&pt(60, 45)));
+    }
+
+    #[test]
+    fn ordered_query_ignores_layout() {
+        let points = generate_points(&square(100), 200, 11);
+        let reversed: Vec<Rect> = points.iter().rev().cloned().collect();
+        let a = QuadTree::from_points(square(100), 2, &points);
+        let b = QuadTree::from_points(square(100), 16, &reversed);
+
+        let range = Rect { x: 10, y: 20, w: 60, h: 50 };
+        let ordered = a.query_ordered(&range);
+        assert_eq!(ordered, b.query_ordered(&range));
+        assert!(ordered.windows(2).all(|w| (w[0].x, w[0].y) <= (w[1].x, w[1].y)));
+    }
+
+    #[test]
+    fn insert_or_log_reports_like_insert() {
+        let mut tree = QuadTree::new(square(10), 4);
+        assert!(tree.insert_or_log(pt(5, 5)));
+        assert!(!tree.insert_or_log(pt(50, 5)));
+        assert_eq!(tree.len(), 1);
+    }
+
+    #[test]
+    fn center_of_mass_averages_points() {
+        let mut tree = QuadTree::new(square(100), 1);
+        assert_eq!(tree.center_of_mass(), None);
+
+        for p in [pt(10, 10), pt(90, 10), pt(10, 70), pt(90, 70)] {
+            tree.insert(p);
+        }
+        assert_eq!(tree.center_of_mass(), Some((50.0, 40.0)));
+    }
+
+    #[test]
+    fn limited_query_caps_results() {
+        let tree = QuadTree::from_points(square(100), 4, &generate_points(&square(100), 200, 5));
+        let range = Rect { x: 0, y: 0, w: 50, h: 50 };
+        let all = tree.query(&range);
+
+        let some = tree.query_limited(&range, 10);
+        assert_eq!(some.len(), 10);
+        assert!(some.iter().all(|p| all.contains(p)));
+        assert!(tree.query_limited(&range, 0).is_empty());
+        assert_eq!(tree.query_limited(&range, all.len() + 5).len(), all.len());
+    }
+
+    #[test]
+    fn touches_is_closed_and_intersects_is_open() {
+        let a = Rect { x: 0, y: 0, w: 10, h: 10 };
+        let edge = Rect { x: 10, y: 0, w: 5, h: 5 };
+        let corner = Rect { x: 10, y: 10, w: 5, h: 5 };
+        let apart = Rect { x: 11, y: 0, w: 5, h: 5 };
+
+        assert!(a.touches(&edge) && edge.touches(&a));
+        assert!(a.touches(&corner));
+        assert!(!a.intersects(&edge) && !a.intersects(&corner));
+        assert!(!a.touches(&apart));
+    }
+
+    #[test]
+    fn query_finds_points_on_range_edges() {
+        let mut tree = QuadTree::new(square(100), 1);
+        for p in [pt(50, 50), pt(10, 10), pt(60, 60), pt(90, 10)] {
+            tree.insert(p);
+        }
+
+        let found = tree.query_ordered(&Rect { x: 0, y: 0, w: 50, h: 50 });
+        assert_eq!(found, vec![pt(10, 10), pt(50, 50)]);
+    }
+
+    #[test]
+    fn quadrant_counts_match_brute_force() {
+        let points = generate_points(&square(100), 200, 5);
+        let tree = QuadTree::from_points(square(100), 1, &points);
+
+        for (px, py) in [(37, 61), (50, 50), (0, 0), (100, 100)] {
+            let mut expected = [0; 4];
+            for p in tree.iter() {
+                expected[(p.x >= px) as usize + 2 * (p.y >= py) as usize] += 1;
+            }
+
+            assert_eq!(tree.quadrant_counts(px, py), expected, "pivot ({}, {})", px, py);
+        }
+    }
+
+    #[test]
+    fn move_point_keeps_id_and_radius() {
+        let mut tree = QuadTree::new(square(100), 1);
+        tree.insert_with_id(7, pt(10, 10));
+        tree.insert_disc(60, 60, 5);
+
+        assert!(tree.move_point(&pt(10, 10), pt(80, 20)));
+        assert!(tree.move_point(&pt(60, 60), pt(30, 70)));
+        assert_eq!(tree.query_ids(&square(100)), vec![(7, pt(80, 20))]);
+        assert_eq!(tree.query_discs(&square(100)), vec![(30, 70, 5)]);
+
+        assert!(!tree.move_point(&pt(80, 20), pt(150, 20)));
+        assert!(tree.contains_point(&pt(80, 20)));
+        assert!(!tree.move_point(&pt(1, 1), pt(2, 2)));
+    }
+
+    #[test]
+    fn move_point_snaps_target() {
+        let mut tree = QuadTree::new(square(100), 4).with_snap_to_grid(10, true);
+        tree.insert(pt(10, 10));
+        tree.insert(pt(40, 40));
+
+        assert!(tree.move_point(&pt(10, 10), pt(22, 18)));
+        assert!(tree.contains_point(&pt(20, 20)));
+        assert!(!tree.move_point(&pt(20, 20), pt(41, 39)));
+        assert!(tree.contains_point(&pt(20, 20)));
+        assert_eq!(tree.len(), 2);
+    }
+
+    #[test]
+    fn query_matches_brute_force() {
+        let points = generate_points(&square(256), 500, 5);
+
+        for soa in [false, true] {
+            let tree = QuadTree::from_points(square(256), 2, &points).with_soa_leaves(soa);
+
+            for range in [square(256), Rect { x: 10, y: 20, w: 140, h: 100 }, Rect { x: 64, y: 64, w: 64, h: 64 }] {
+                let mut expected: Vec<Rect> = points.iter().filter(|p| range.contains(p)).cloned().collect();
+                expected.sort_by_key(|p| (p.x, p.y));
+
+                assert_eq!(tree.query_ordered(&range), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn insert_all_returns_rejected_points() {
+        let mut tree = QuadTree::new(square(100), 2);
+        let rejected = tree.insert_all(vec![pt(10, 10), pt(-1, 5), pt(50, 50), pt(5, 101)]);
+
+        assert_eq!(rejected, vec![pt(-1, 5), pt(5, 101)]);
+        assert_eq!(tree.len(), 2);
+    }
+
+    #[test]
+    fn rebalance_keeps_points_and_flattens_clusters() {
+        let mut points = generate_points(&Rect { x: 0, y: 0, w: 16, h: 16 }, 360, 5);
+        points.extend(generate_points(&square(1024), 40, 6));
+        let mut tree = QuadTree::from_points(square(1024), 4, &points);
+
+        let before = tree.query_ordered(&square(1024));
+        let depth = tree.depth();
+        tree.rebalance();
+
+        assert_eq!(tree.query_ordered(&square(1024)), before);
+        assert_eq!(tree.len(), points.len());
+        assert!(tree.depth() < depth, "{} -> {}", depth, tree.depth());
+        tree.debug_check_invariants();
+    }
+
+    #[test]
+    fn query_points_builds_and_queries() {
+        let points = vec![pt(10, 10), pt(60, 60), pt(200, 5)];
+        let mut found = query_points(square(100), 1, &points, &Rect { x: 0, y: 0, w: 20, h: 20 });
+        assert_eq!(found, vec![pt(10, 10)]);
+
+        found = query_points(square(100), 1, &points, &square(300));
+        assert_eq!(found.len(), 2);
+    }
+
+    #[test]
+    fn diff_reports_added_and_removed() {
+        let before = QuadTree::from_points(square(100), 2, &[pt(1, 1), pt(2, 2), pt(3, 3)]);
+        let mut after = before.clone();
+        after.remove(&pt(2, 2));
+        after.insert(pt(9, 9));
+
+        assert_eq!(before.diff(&after), (vec![pt(9, 9)], vec![pt(2, 2)]));
+        assert_eq!(before.diff(&before), (vec![], vec![]));
+    }
+
+    #[test]
+    fn max_points_evicts_oldest() {
+        let mut tree = QuadTree::new(square(100), 2).with_max_points(10);
+        let points: Vec<Rect> = (0..30).map(|i| pt(i * 3 % 100, i * 7 % 100)).collect();
+
+        for p in &points {
+            tree.insert(p.clone());
+            assert!(tree.len() <= 10);
+        }
+
+        assert_eq!(tree.len(), 10);
+        assert_eq!(tree.iter().len(), 10);
+        assert!(points[..20].iter().all(|p| !tree.contains_point(p)));
+        assert!(tree.contains_all(&points[20..]));
+    }
+
+    #[test]
+    fn rect_display() {
+        assert_eq!(Rect { x: -3, y: 4, w: 10, h: 20 }.to_string(), "(-3, 4, 10×20)");
+    }
+
+    #[test]
+    fn densest_cells_are_sorted_by_count() {
+        let mut tree = QuadTree::new(square(100), 2).with_min_cell_size(25);
+        for i in 0..6 {
+            tree.insert(pt(5 + i, 5));
+        }
+        for i in 0..3 {
+            tree.insert(pt(80 + i, 80));
+        }
+        tree.insert(pt(80, 10));
+
+        let cells = tree.densest_cells(2);
+        assert_eq!(cells.len(), 2);
+        assert_eq!(cells[0].1, 6);
+        assert_eq!(cells[1].1, 3);
+        assert!(cells[0].0.contains(&pt(5, 5)));
+        assert_eq!(tree.densest_cells(100).len(), tree.densest_cells(usize::MAX).len());
+    }
+
+    #[test]
+    fn query_toward_returns_nearest_first() {
+        let points = generate_points(&square(200), 300, 9);
+        let tree = QuadTree::from_points(square(200), 4, &points);
+        let range = Rect { x: 20, y: 20, w: 150, h: 150 };
+        let focus = pt(120, 40);
+
+        let found = tree.query_toward(&range, (focus.x, focus.y), 15);
+        let mut expected: Vec<i64> = tree.query(&range).iter().map(|p| p.distance_sq(&focus)).collect();
+        expected.sort_unstable();
+
+        assert_eq!(found.len(), 15);
+        assert!(found.iter().all(|p| range.contains(p)));
+        assert_eq!(found.iter().map(|p| p.distance_sq(&focus)).collect::<Vec<_>>(), expected[..15]);
+    }
+
+    #[test]
+    fn cells_at_depth_lists_one_level() {
+        let tree = QuadTree::with_presplit(square(100), 4, 2);
+
+        assert_eq!(tree.cells_at_depth(0), vec![square(100)]);
+        assert_eq!(tree.cells_at_depth(1).to_vec(), square(100).quadrants().to_vec());
+        let level = tree.cells_at_depth(2);
+        assert_eq!(level.len(), 16);
+        assert_eq!(level.iter().map(|c| c.w as i64 * c.h as i64).sum::<i64>(), 100 * 100);
+        assert!(tree.cells_at_depth(3).is_empty());
+    }
+
+    #[test]
+    fn shrink_root_fits_stored_points() {
+        let points = generate_points(&Rect { x: 300, y: 300, w: 20, h: 20 }, 50, 4);
+        let mut tree = QuadTree::from_points(square(1024), 4, &points);
+        tree.shrink_root();
+
+        let b = tree.boundary().clone();
+        assert!(b.w <= 64 && b.h <= 64, "{}", b);
+        assert!(points.iter().all(|p| b.contains(p)));
+        assert_eq!(tree.len(), points.len());
+        assert!(tree.contains_all(&points));
+    }
+
+    #[test]
+    fn shrink_root_keeps_sized_rects_inside() {
+        let mut tree = QuadTree::new(square(1024), 4);
+        tree.insert(pt(300, 300));
+        tree.insert_rect(Rect { x: 310, y: 310, w: 200, h: 10 });
+        tree.shrink_root();
+
+        assert!(tree.boundary().contains_rect(&Rect { x: 310, y: 310, w: 200, h: 10 }));
+        assert_eq!(tree.query_overlaps(&Rect { x: 400, y: 305, w: 10, h: 10 }).len(), 1);
+        assert!(tree.contains_point(&pt(300, 300)));
+    }
+
+    #[test]
+    fn max_overlap_counts_covering_rects() {
+        let mut tree = QuadTree::new(square(100), 2);
+        tree.insert_rect(Rect { x: 0, y: 0, w: 30, h: 30 });
+        tree.insert_rect(Rect { x: 10, y: 10, w: 30, h: 30 });
+        tree.insert_rect(Rect { x: 20, y: 20, w: 30, h: 30 });
+        tree.insert_rect(Rect { x: 60, y: 60, w: 10, h: 10 });
+
+        assert_eq!(tree.max_overlap(&square(100)), 3);
+        assert_eq!(tree.max_overlap(&Rect { x: 0, y: 0, w: 15, h: 15 }), 2);
+        assert_eq!(tree.max_overlap(&Rect { x: 55, y: 55, w: 20, h: 20 }), 1);
+        assert_eq!(tree.max_overlap(&Rect { x: 80, y: 0, w: 10, h: 10 }), 0);
+    }
+
+    #[test]
+    fn max_overlap_counts_rects_starting_left_of_range() {
+        let mut tree = QuadTree::new(square(100), 2);
+        tree.insert_rect(Rect { x: 0, y: 0, w: 10, h: 10 });
+
+        assert_eq!(tree.max_overlap(&Rect { x: 5, y: 0, w: 3, h: 10 }), 1);
+    }
+
+    #[test]
+    fn geometry_only_matches_node_layout() {
+        let tree = QuadTree::from_points(square(100), 1, &[pt(10, 10), pt(60, 60)]);
+        let geometry = tree.geometry_only();
+
+        assert_eq!(geometry.len(), tree.node_count());
+        assert_eq!(geometry[0], (square(100), 0));
+        assert_eq!(
+            geometry[1..].iter().map(|(b, _)| b.clone()).collect::<Vec<_>>(),
+            square(100).quadrants().to_vec()
+        );
+        assert!(geometry[1..].iter().all(|(_, depth)| *depth == 1));
+    }
+
+    #[test]
+    fn point_converts_to_unit_rect() {
+        let mut tree = QuadTree::new(square(100), 4);
+        assert!(tree.insert(Point { x: 3, y: 4 }));
+
+        assert_eq!(Rect::from(Point { x: 3, y: 4 }), Rect { x: 3, y: 4, w: 1, h: 1 });
+        assert_eq!(Point::from(Rect { x: 3, y: 4, w: 9, h: 9 }), Point { x: 3, y: 4 });
+        assert_eq!(tree.query_positions(&square(100)), vec![Point { x: 3, y: 4 }]);
+    }
+
+    #[test]
+    fn query_recent_filters_by_insertion_seq() {
+        let mut tree = QuadTree::new(square(100), 1);
+        tree.insert(pt(10, 10));
+        tree.insert(pt(60, 60));
+        let since = tree.insertion_seq();
+        tree.insert(pt(20, 20));
+        tree.insert(pt(200, 20));
+        tree.insert(pt(70, 70));
+
+        assert_eq!(since, 2);
+        assert_eq!(tree.insertion_seq(), 4);
+        let mut recent = tree.query_recent(&square(100), since);
+        recent.sort_by_key(|p| (p.x, p.y));
+        assert_eq!(recent, vec![pt(20, 20), pt(70, 70)]);
+        assert_eq!(tree.query_recent(&Rect { x: 0, y: 0, w: 30, h: 30 }, since), vec![pt(20, 20)]);
+    }
+
+    #[test]
+    fn fold_visits_every_point() {
+        let points = generate_points(&square(100), 100, 2);
+        let tree = QuadTree::from_points(square(100), 3, &points);
+
+        let (count, sum) = tree.fold((0, 0i64), |(n, s), p| (n + 1, s + p.x as i64));
+        assert_eq!(count, points.len());
+        assert_eq!(sum, points.iter().map(|p| p.x as i64).sum::<i64>());
+        assert_eq!(QuadTree::new(square(100), 3).fold(0, |n, _| n + 1), 0);
+    }
+
+    #[test]
+    fn median_split_follows_points() {
+        let mut tree = QuadTree::new(square(100), 3).with_split_strategy(SplitStrategy::Median);
+        for p in [pt(10, 10), pt(20, 20), pt(30, 30), pt(40, 40)] {
+            tree.insert(p);
+        }
+
+        let [nw, ne, _, se] = tree.children().unwrap();
+        assert_eq!(nw.boundary(), &Rect { x: 0, y: 0, w: 20, h: 20 });
+        assert_eq!(ne.boundary().x, 20);
+        assert_eq!(se.boundary(), &Rect { x: 20, y: 20, w: 80, h: 80 });
+        assert_eq!(tree.len(), 4);
+        assert_eq!(tree.query_ordered(&square(100)).len(), 4);
+    }
+
+    #[test]
+    fn query_with_cells_pairs_points_with_leaves() {
+        let points = generate_points(&square(100), 60, 8);
+        let tree = QuadTree::from_points(square(100), 2, &points);
+        let range = Rect { x: 20, y: 20, w: 50, h: 50 };
+
+        let found = tree.query_with_cells(&range);
+        assert_eq!(found.len(), tree.query(&range).len());
+        for (point, cell) in &found {
+            assert!(range.contains(point) && cell.contains(point));
+            assert_eq!(tree.leaf_boundary(point.x, point.y), Some(cell));
+        }
+    }
+
+    #[test]
+    fn query_discs_finds_discs_reaching_into_range() {
+        let mut tree = QuadTree::new(square(100), 1);
+        tree.insert_disc(10, 10, 5);
+        tree.insert_disc(80, 80, 15);
+        tree.insert(pt(50, 50));
+
+        let range = Rect { x: 60, y: 60, w: 10, h: 10 };
+        assert_eq!(tree.query_discs(&range), vec![(80, 80, 15)]);
+        assert!(tree.query(&range).is_empty());
+        assert_eq!(tree.query_discs(&square(100)).len(), 2);
+    }
+
+    #[test]
+    fn auto_capacity_scales_with_density() {
+        let boundary = square(1024);
+
+        assert_eq!(QuadTree::auto_capacity(0, &boundary), 4);
+        assert_eq!(QuadTree::auto_capacity(usize::MAX / 2, &boundary), 64);
+        assert!(QuadTree::auto_capacity(20_000, &boundary) > QuadTree::auto_capacity(2_000, &boundary));
+        assert_eq!(QuadTree::auto_capacity(100, &square(16)), 64);
+    }
+
+    #[test]
+    fn any_in_range_agrees_with_query() {
+        let tree = QuadTree::from_points(square(100), 2, &generate_points(&square(100), 30, 4));
+
+        for range in [square(100), Rect { x: 40, y: 40, w: 3, h: 3 }, Rect { x: 200, y: 0, w: 5, h: 5 }] {
+            assert_eq!(tree.any_in_range(&range), !tree.query(&range).is_empty());
+        }
+        assert!(!QuadTree::new(square(100), 2).any_in_range(&square(100)));
+    }
+
+    #[test]
+    fn clamp_to_boundary_trims_ranges() {
+        let tree = QuadTree::new(Rect { x: 10, y: 10, w: 100, h: 100 }, 4);
+
+        assert_eq!(tree.clamp_to_boundary(&Rect { x: 0, y: 50, w: 200, h: 20 }), Rect { x: 10, y: 50, w: 100, h: 20 });
+        assert_eq!(tree.clamp_to_boundary(&Rect { x: 20, y: 20, w: 5, h: 5 }), Rect { x: 20, y: 20, w: 5, h: 5 });
+        assert_eq!(tree.clamp_to_boundary(&Rect { x: 300, y: 0, w: 5, h: 5 }), Rect { x: 300, y: 0, w: 0, h: 0 });
+    }
+
+    #[test]
+    fn clamp_to_boundary_normalizes_negative_sizes() {
+        let tree = QuadTree::new(square(100), 4);
+
+        assert_eq!(tree.clamp_to_boundary(&Rect { x: 50, y: 120, w: -20, h: -40 }), Rect { x: 30, y: 80, w: 20, h: 20 });
+    }
+
+    #[test]
+    fn normalized_flips_negative_sizes() {
+        let expected = Rect { x: 20, y: 40, w: 40, h: 30 };
+        let drags = [
+            Rect { x: 60, y: 70, w: -40, h: -30 },
+            Rect { x: 60, y: 40, w: -40, h: 30 },
+            Rect { x: 20, y: 70, w: 40, h: -30 },
+            expected.clone(),
+        ];
+        for drag in &drags {
+            assert_eq!(drag.normalized(), expected);
+            assert_eq!(drag.normalized().normalized(), expected);
+        }
+
+        let drag = &drags[0];
+
+        let tree = QuadTree::from_points(square(100), 2, &[pt(30, 50), pt(70, 50)]);
+        assert_eq!(tree.query(&drag.normalized()), vec![pt(30, 50)]);
+    }
+
+    #[test]
+    fn query_indexed_reports_insertion_order() {
+        let mut tree = QuadTree::new(square(100), 1);
+        for p in [pt(60, 60), pt(200, 0), pt(10, 10), pt(70, 10)] {
+            tree.insert(p);
+        }
+
+        let mut found = tree.query_indexed(&square(100));
+        found.sort_by_key(|(i, _)| *i);
+        assert_eq!(found, vec![(0, pt(60, 60)), (1, pt(10, 10)), (2, pt(70, 10))]);
+    }
+
+    #[test]
+    fn straddling_rects_follow_policy() {
+        let big = Rect { x: 40, y: 40, w: 20, h: 20 };
+
+        for policy in [StraddlePolicy::Ancestor, StraddlePolicy::Duplicate] {
+            let mut tree = QuadTree::new(square(100), 1).with_straddle_policy(policy);
+            tree.insert_rect(big.clone());
+            tree.insert_rect(Rect { x: 5, y: 5, w: 5, h: 5 });
+            tree.insert_rect(Rect { x: 70, y: 10, w: 5, h: 5 });
+
+            let copies = tree.fold(0, |n, r| n + (*r == big) as usize);
+            match policy {
+                StraddlePolicy::Ancestor => assert!(copies == 1 && tree.points().contains(&big)),
+                StraddlePolicy::Duplicate => assert!(copies == 4 && !tree.points().contains(&big)),
+            }
+            assert_eq!(tree.query_overlaps(&Rect { x: 45, y: 45, w: 10, h: 10 }), vec![big.clone()]);
+            assert_eq!(tree.query_overlaps(&square(100)).len(), 3);
+        }
+    }
+
+    #[test]
+    fn rebuilds_keep_one_set_of_duplicates() {
+        let big = Rect { x: 40, y: 40, w: 20, h: 20 };
+        let mut tree = QuadTree::new(square(100), 1).with_straddle_policy(StraddlePolicy::Duplicate);
+        tree.insert_rect(big.clone());
+        tree.insert(pt(5, 5));
+        tree.insert(pt(90, 90));
+        let copies = |tree: &QuadTree| tree.fold(0, |n, r| n + (*r == big) as usize);
+
+        tree.rebalance();
+        let after_rebalance = copies(&tree);
+        tree.rebalance();
+        assert_eq!(copies(&tree), after_rebalance);
+        assert_eq!(tree.query_overlaps(&square(100)).len(), 3);
+
+        tree.shrink_root();
+        assert!(copies(&tree) <= 4);
+        assert_eq!(tree.query_overlaps(&square(100)).len(), 3);
+        tree.debug_check_invariants();
+    }
+
+    #[test]
+    fn reset_moves_tree_to_new_region() {
+        let mut tree = QuadTree::new(square(100), 1).with_duplicate_policy(DuplicatePolicy::RejectExact);
+        for p in [pt(10, 10), pt(60, 60), pt(70, 10)] {
+            tree.insert(p);
+        }
+
+        let region = Rect { x: 1000, y: 1000, w: 50, h: 50 };
+        tree.reset(region.clone(), 2);
+        assert!(tree.is_empty() && tree.children().is_none());
+        assert_eq!(tree.boundary(), &region);
+        assert!(!tree.insert(pt(10, 10)));
+        assert!(tree.insert(pt(1010, 1010)));
+        assert!(!tree.insert(pt(1010, 1010)));
+    }
+
+    #[test]
+    fn memory_bytes_grows_with_contents() {
+        let empty = QuadTree::new(square(256), 4);
+        let full = QuadTree::from_points(square(256), 4, &generate_points(&square(256), 500, 1));
+
+        assert!(empty.memory_bytes() >= mem::size_of::<QuadTree>());
+        assert!(full.memory_bytes() >= full.node_count() * mem::size_of::<QuadTree>() + 500 * mem::size_of::<Rect>());
+    }
+
+    #[test]
+    fn density_grid_bins_points() {
+        let tree = QuadTree::from_points(square(100), 2, &[pt(10, 10), pt(20, 20), pt(90, 10), pt(100, 100), pt(55, 60)]);
+
+        assert_eq!(tree.density_grid(&square(100), 2, 2), vec![2, 1, 0, 2]);
+        assert_eq!(tree.density_grid(&square(100), 1, 1), vec![5]);
+        assert!(tree.density_grid(&square(100), 0, 3).is_empty());
+    }
+
+    #[test]
+    fn contains_xy_is_inclusive() {
+        let rect = Rect { x: 10, y: 20, w: 5, h: 5 };
+
+        assert!(rect.contains_xy(10, 20) && rect.contains_xy(15, 25));
+        assert!(!rect.contains_xy(9, 20) && !rect.contains_xy(10, 26));
+        assert_eq!(rect.contains_xy(12, 22), rect.contains(&pt(12, 22)));
+    }
+
+    #[test]
+    fn trace_skips_empty_subtrees() {
+        let mut tree = QuadTree::with_presplit(square(100), 4, 2);
+        tree.insert(pt(10, 10));
+
+        let (points, visited) = tree.query_with_trace(&square(100));
+        assert_eq!(points, vec![pt(10, 10)]);
+        assert_eq!(visited, vec![square(100), Rect { x: 0, y: 0, w: 50, h: 50 }, Rect { x: 0, y: 0, w: 25, h: 25 }]);
+        assert!(QuadTree::with_presplit(square(100), 4, 2).query_with_trace(&square(100)).1.is_empty());
+    }
+
+    #[test]
+    fn pruning_ratio_is_fraction_of_nodes_visited() {
+        let mut tree = QuadTree::with_presplit(square(100), 4, 1);
+        for p in [pt(10, 10), pt(60, 10), pt(10, 60), pt(60, 60)] {
+            tree.insert(p);
+        }
+
+        assert_eq!(tree.pruning_ratio(&[]), 0.0);
+        assert_eq!(tree.pruning_ratio(&[square(100)]), 1.0);
+        assert_eq!(tree.pruning_ratio(&[Rect { x: 0, y: 0, w: 10, h: 10 }]), 0.4);
+    }
+
+    #[test]
+    fn far_edge_points_are_stored_and_found() {
+        let mut tree = QuadTree::new(square(100), 1);
+        for p in [pt(100, 100), pt(100, 0), pt(0, 100), pt(50, 50)] {
+            assert!(tree.insert(p));
+        }
+        assert!(!tree.insert(pt(101, 100)));
+
+        assert!(tree.contains_point(&pt(100, 100)));
+        assert_eq!(tree.query(&Rect { x: 90, y: 90, w: 10, h: 10 }), vec![pt(100, 100)]);
+        assert!(tree.remove(&pt(100, 100)));
+        assert_eq!(tree.len(), 3);
+    }
+
+    #[test]
+    fn capacity_one_tree_keeps_one_point_per_leaf() {
+        let mut points = generate_points(&square(256), 300, 12);
+        points.sort_by_key(|p| (p.x, p.y));
+        points.dedup();
+        let tree = QuadTree::from_points(square(256), 1, &points);
+
+        assert_eq!(tree.len(), points.len());
+        assert!(tree.overfull_leaves().is_empty());
+        assert!(nodes(&tree).iter().all(|node| node.points().len() <= 1));
+        assert!(nodes(&tree).iter().filter(|node| node.children().is_some()).all(|node| node.points().is_empty()));
+        assert_eq!(tree.query_ordered(&square(256)), points);
+    }
+
+    #[test]
+    fn subtree_for_descends_to_covering_node() {
+        let tree = QuadTree::from_points(square(100), 1, &[pt(10, 10), pt(40, 10), pt(60, 60)]);
+        let range = Rect { x: 30, y: 5, w: 10, h: 10 };
+
+        let sub = tree.subtree_for(&range);
+        assert_eq!(sub.boundary(), &Rect { x: 25, y: 0, w: 25, h: 25 });
+        assert_eq!(sub.query(&range), tree.query(&range));
+        assert!(std::ptr::eq(tree.subtree_for(&Rect { x: 40, y: 40, w: 20, h: 20 }), &tree));
+    }
+
+    #[test]
+    fn overfull_leaves_lists_unsplittable_cells() {
+        let mut tree = QuadTree::new(square(8), 2).with_min_cell_size(4);
+        for _ in 0..5 {
+            tree.insert(pt(1, 1));
+        }
+        tree.insert(pt(7, 7));
+
+        assert_eq!(tree.overfull_leaves(), vec![(Rect { x: 0, y: 0, w: 4, h: 4 }, 5)]);
+        assert!(QuadTree::from_points(square(8), 2, &[pt(1, 1), pt(7, 7)]).overfull_leaves().is_empty());
+    }
+
+    #[test]
+    fn query_many_matches_separate_queries() {
+        let tree = QuadTree::from_points(square(256), 3, &generate_points(&square(256), 400, 10));
+        let ranges = [square(256), Rect { x: 10, y: 10, w: 40, h: 90 }, Rect { x: 300, y: 0, w: 5, h: 5 }, Rect { x: 128, y: 128, w: 0, h: 0 }];
+
+        let results = tree.query_many(&ranges);
+        assert_eq!(results.len(), ranges.len());
+        for (range, mut found) in ranges.iter().zip(results) {
+            found.sort_by_key(|p| (p.x, p.y));
+            assert_eq!(found, tree.query_ordered(range));
+        }
+        assert!(tree.query_many(&[]).is_empty());
+    }
+
+    #[test]
+    fn snap_to_grid_rounds_and_dedups() {
+        let mut tree = QuadTree::new(square(100), 4).with_snap_to_grid(10, true);
+
+        assert!(tree.insert(pt(14, 26)));
+        assert!(!tree.insert(pt(6, 34)));
+        assert!(tree.insert(pt(15, 25)));
+        assert_eq!(tree.query_ordered(&square(100)), vec![pt(10, 30), pt(20, 30)]);
+
+        let mut coarse = QuadTree::new(square(100), 4).with_snap_to_grid(10, false);
+        coarse.insert(pt(14, 26));
+        coarse.insert(pt(6, 34));
+        assert_eq!(coarse.len(), 2);
+        assert_eq!(coarse.snapped(&pt(-6, 4)), pt(-10, 0));
+    }
+
+    #[test]
+    fn cached_count_tracks_removals_and_evictions() {
+        let points = generate_points(&square(100), 50, 13);
+        let mut tree = QuadTree::new(square(100), 2).with_max_points(40);
+        for p in &points {
+            tree.insert(p.clone());
+        }
+        assert_eq!((tree.len(), tree.iter().len(), tree.iter().count()), (40, 40, 40));
+
+        tree.remove(&points[45]);
+        tree.insert_with_id(1, pt(5, 5));
+        tree.remove_by_id(1);
+        tree.remove(&pt(-5, 5));
+
+        let mut iter = tree.iter();
+        assert_eq!(iter.size_hint(), (39, Some(39)));
+        iter.next();
+        assert_eq!(iter.len(), 38);
+        assert_eq!(tree.iter().count(), tree.len());
+        tree.debug_check_invariants();
+    }
+
+    #[test]
+    fn replay_rebuilds_recorded_tree() {
+        let mut tree = QuadTree::new(square(256), 2)
+            .with_max_points(30)
+            .with_snap_to_grid(2, true)
+            .with_straddle_policy(StraddlePolicy::Duplicate)
+            .with_recording();
+
+        for p in generate_points(&square(256), 40, 14) {
+            tree.insert(p);
+        }
+        tree.insert_with_id(5, pt(100, 100));
+        tree.insert_disc(30, 30, 4);
+        tree.insert_rect(Rect { x: 120, y: 120, w: 20, h: 20 });
+        tree.remove_by_id(5);
+        tree.move_point(&pt(30, 30), pt(41, 41));
+        tree.prune_empty();
+        tree.rebalance();
+        tree.shrink_root();
+
+        let replayed = tree.replay(tree.ops());
+        assert!(replayed == tree);
+        assert_eq!(replayed.ops(), tree.ops());
+        assert_eq!(replayed.query_discs(&square(256)), tree.query_discs(&square(256)));
+        assert!(tree.ops().contains(&Op::Move(pt(30, 30), pt(42, 42))));
+    }
+
+    #[test]
+    fn replay_starts_from_recorded_origin() {
+        let mut tree = QuadTree::new(square(100), 4).with_recording();
+        tree.insert(pt(1, 1));
+        tree.clear();
+        tree.reset(Rect { x: 500, y: 500, w: 50, h: 50 }, 1);
+        tree.insert(pt(510, 510));
+        tree.insert(pt(540, 540));
+        tree.remove(&pt(510, 510));
+
+        let replayed = tree.replay(tree.ops());
+        assert!(replayed == tree);
+        assert_eq!(replayed.boundary(), &Rect { x: 500, y: 500, w: 50, h: 50 });
+        assert!(QuadTree::new(square(100), 4).ops().is_empty());
+    }
+
+    #[test]
+    fn checked_query_rejects_ranges_outside_boundary() {
+        let tree = QuadTree::from_points(square(100), 2, &[pt(10, 10)]);
+        let outside = Rect { x: 200, y: 0, w: 10, h: 10 };
+
+        assert_eq!(tree.checked_query(&outside), Err(OutOfBounds { range: outside.clone(), boundary: square(100) }));
+        assert_eq!(tree.checked_query(&Rect { x: 50, y: 50, w: 10, h: 10 }), Ok(vec![]));
+        assert_eq!(tree.checked_query(&Rect { x: -10, y: -10, w: 30, h: 30 }), Ok(vec![pt(10, 10)]));
+    }
+
+    #[test]
+    fn query_returns_empty_vec_when_nothing_matches() {
+        let empty = QuadTree::new(square(100), 2);
+        let tree = QuadTree::from_points(square(100), 2, &[pt(10, 10)]);
+
+        assert!(empty.query(&square(100)).is_empty());
+        assert!(tree.query(&Rect { x: 50, y: 50, w: 10, h: 10 }).is_empty());
+        assert!(tree.query(&Rect { x: 500, y: 500, w: 10, h: 10 }).is_empty());
+    }
+
+    #[test]
+    fn chunk_index_tiles_the_plane() {
+        let chunk = Rect { x: 100, y: -50, w: 100, h: 100 };
+        let mut tree = QuadTree::new(chunk.clone(), 2);
+        for p in [pt(150, -40), pt(110, -45), pt(120, 20), pt(190, 40)] {
+            assert!(tree.insert(p));
+        }
+        assert!(!tree.insert(pt(50, 0)));
+
+        let children = tree.children().expect("four points split a capacity 2 root");
+        let bounds: Vec<Rect> = children.iter().map(|child| child.boundary().clone()).collect();
+        assert_eq!(bounds, vec![
+            Rect { x: 100, y: -50, w: 50, h: 50 },
+            Rect { x: 150, y: -50, w: 50, h: 50 },
+            Rect { x: 100, y: 0, w: 50, h: 50 },
+            Rect { x: 150, y: 0, w: 50, h: 50 },
+        ]);
+        assert_eq!(tree.query_ordered(&Rect { x: 100, y: -50, w: 49, h: 49 }), vec![pt(110, -45)]);
+        assert_eq!(tree.query_ordered(&Rect { x: 100, y: 0, w: 100, h: 50 }), vec![pt(120, 20), pt(190, 40)]);
+        assert_eq!(tree.query(&chunk).len(), 4);
+
+        assert_eq!(tree.chunk_index(150, 0), Some((0, 0)));
+        assert_eq!(tree.chunk_index(99, -51), Some((-1, -1)));
+        assert_eq!(tree.chunk_index(350, 150), Some((2, 2)));
+        assert_eq!(QuadTree::new(Rect { x: 0, y: 0, w: 0, h: 10 }, 2).chunk_index(0, 0), None);
+    }
+
+    #[test]
+    fn empty_tree_query_short_circuits() {
+        let tree = QuadTree::with_presplit(square(256), 4, 3);
+
+        assert_eq!(tree.query(&square(256)).capacity(), 0);
+        assert!(tree.query_with_trace(&square(256)).1.is_empty());
+        assert!(tree.query_many(&[square(256)])[0].is_empty());
+        assert_eq!(tree.count_in_range(&square(256)), 0);
+    }
+
+    #[test]
+    fn total_edge_length_sums_node_perimeters() {
+        assert_eq!(QuadTree::new(Rect { x: 0, y: 0, w: 100, h: 50 }, 1).total_edge_length(), 300);
+        assert_eq!(QuadTree::with_presplit(square(100), 1, 1).total_edge_length(), 400 + 4 * 200);
+        assert_eq!(QuadTree::with_presplit(square(100), 1, 2).total_edge_length(), 400 + 4 * 200 + 16 * 100);
+    }
+
+    #[test]
+    fn query_iter_yields_query_results() {
+        let tree = QuadTree::from_points(square(256), 3, &generate_points(&square(256), 300, 15));
+        let range = Rect { x: 30, y: 40, w: 100, h: 120 };
+
+        let mut lazy: Vec<Rect> = tree.query_iter(&range).cloned().collect();
+        lazy.sort_by_key(|p| (p.x, p.y));
+        assert_eq!(lazy, tree.query_ordered(&range));
+        assert_eq!(tree.query_iter(&range).take(3).count(), 3);
+        assert!(tree.query_iter(&Rect { x: 300, y: 0, w: 5, h: 5 }).next().is_none());
+    }
+
+    #[test]
+    fn insert_counters_count_work_and_reset() {
+        let mut tree = QuadTree::new(square(100), 1);
+        tree.insert(pt(10, 10));
+        assert_eq!(tree.take_counters(), InsertCounters { contains_checks: 1, subdivisions: 0, descents: 0 });
+
+        tree.insert(pt(60, 60));
+        let counters = tree.take_counters();
+        assert_eq!(counters.subdivisions, 1);
+        assert_eq!(counters.descents, 1);
+        assert!(counters.contains_checks >= 3);
+        assert_eq!(tree.take_counters(), InsertCounters::default());
+    }
+
+    #[test]
+    fn contains_all_requires_every_point() {
+        let tree = QuadTree::from_points(square(100), 1, &[pt(10, 10), pt(60, 60), pt(90, 5)]);
+
+        assert!(tree.contains_all(&[pt(60, 60), pt(10, 10)]));
+        assert!(!tree.contains_all(&[pt(60, 60), pt(11, 10)]));
+        assert!(tree.contains_all(&[]));
+    }
+
+    #[test]
+    fn map_points_remaps_and_drops_outside() {
+        let mut tree = QuadTree::new(square(100), 1);
+        tree.insert_with_id(3, pt(10, 10));
+        tree.insert(pt(20, 60));
+        tree.insert(pt(80, 80));
+
+        let shifted = tree.map_points(|p| Rect { x: p.x + 25, y: p.y, ..p });
+        assert_eq!(shifted.query_ordered(&square(100)), vec![pt(35, 10), pt(45, 60)]);
+        assert_eq!(shifted.query_ids(&square(100)), vec![(3, pt(35, 10))]);
+        assert_eq!(shifted.len(), 2);
+    }
+
+    #[test]
+    fn points_outside_lists_what_a_new_boundary_drops() {
+        let mut tree = QuadTree::new(square(100), 1);
+        tree.insert(pt(10, 10));
+        tree.insert(pt(80, 80));
+        tree.insert_rect(Rect { x: 40, y: 40, w: 20, h: 20 });
+
+        let mut outside = tree.points_outside(&Rect { x: 0, y: 0, w: 50, h: 50 });
+        outside.sort_by_key(|p| (p.x, p.y));
+        assert_eq!(outside, vec![Rect { x: 40, y: 40, w: 20, h: 20 }, pt(80, 80)]);
+        assert!(tree.points_outside(&square(100)).is_empty());
+    }
+
+    #[test]
+    fn from_points_skips_points_outside() {
+        let points = [pt(10, 10), pt(-5, 10), pt(60, 60), pt(10, 300)];
+        let tree = QuadTree::from_points(square(100), 1, &points);
+
+        assert_eq!(tree.len(), 2);
+        assert_eq!(tree.query_ordered(&Rect { x: -100, y: -100, w: 500, h: 500 }), vec![pt(10, 10), pt(60, 60)]);
+
+        let unchecked = QuadTree::from_points_unchecked(square(100), 1, &[pt(10, 10), pt(60, 60)]);
+        assert!(unchecked == tree);
+    }
+
+    #[test]
+    fn query_unique_ids_keeps_one_entry_per_id() {
+        let mut tree = QuadTree::new(square(100), 1);
+        tree.insert_with_id(1, pt(10, 10));
+        tree.insert_with_id(1, pt(60, 60));
+        tree.insert_with_id(2, pt(70, 10));
+        tree.insert(pt(20, 20));
+
+        let unique = tree.query_unique_ids(&square(100));
+        let mut ids: Vec<u64> = unique.iter().map(|(id, _)| *id).collect();
+        ids.sort_unstable();
+        assert_eq!(ids, vec![1, 2]);
+        assert_eq!(tree.query_ids(&square(100)).len(), 3);
+    }
+
+    #[test]
+    fn nearest_to_rect_measures_edge_distance() {
+        let tree = QuadTree::from_points(square(100), 1, &[pt(10, 10), pt(60, 50), pt(90, 90)]);
+
+        assert_eq!(tree.nearest_to_rect(&Rect { x: 40, y: 40, w: 10, h: 10 }), Some((pt(60, 50), 10)));
+        assert_eq!(tree.nearest_to_rect(&Rect { x: 0, y: 0, w: 20, h: 20 }), Some((pt(10, 10), 0)));
+        assert_eq!(tree.nearest_to_rect(&Rect { x: 93, y: 94, w: 5, h: 5 }), Some((pt(90, 90), 5)));
+        assert_eq!(QuadTree::new(square(100), 1).nearest_to_rect(&square(10)), None);
+    }
+
+    #[test]
+    fn soa_leaves_stay_in_step_with_points() {
+        let points = generate_points(&square(256), 400, 16);
+        let mut plain = QuadTree::from_points(square(256), 150, &points);
+        let mut soa = plain.clone().with_soa_leaves(true);
+
+        for p in &points[..100] {
+            plain.remove(p);
+            soa.remove(p);
+        }
+        soa.insert(pt(5, 5));
+        plain.insert(pt(5, 5));
+
+        soa.debug_check_invariants();
+        for range in [Rect { x: 0, y: 0, w: 100, h: 100 }, Rect { x: 50, y: 120, w: 200, h: 30 }] {
+            assert_eq!(soa.query_ordered(&range), plain.query_ordered(&range));
+        }
+        assert!(nodes(&soa).iter().all(|node| node.xs.len() == node.points.len()));
+    }
+
+    #[test]
+    fn is_balanced_compares_leaf_depths() {
+        assert!(QuadTree::new(square(256), 1).is_balanced(1.0));
+        assert!(QuadTree::with_presplit(square(256), 1, 3).is_balanced(1.0));
+
+        let points = generate_points(&Rect { x: 0, y: 0, w: 8, h: 8 }, 40, 17);
+        let lopsided = QuadTree::from_points(square(256), 1, &points);
+        assert!(lopsided.depth() >= 4);
+        assert!(!lopsided.is_balanced(2.0));
+        assert!(lopsided.is_balanced(lopsided.depth() as f32));
+    }
+
+    #[test]
+    fn quadrants_tile_the_rect() {
+        let even = square(100).quadrants();
+        assert_eq!(even[0], Rect { x: 0, y: 0, w: 50, h: 50 });
+        assert_eq!(even[3], Rect { x: 50, y: 50, w: 50, h: 50 });
+
+        let odd = Rect { x: 10, y: 20, w: 7, h: 5 }.quadrants();
+        assert_eq!(odd, [
+            Rect { x: 10, y: 20, w: 3, h: 2 },
+            Rect { x: 13, y: 20, w: 4, h: 2 },
+            Rect { x: 10, y: 22, w: 3, h: 3 },
+            Rect { x: 13, y: 22, w: 4, h: 3 },
+        ]);
+        assert_eq!(odd.iter().map(|q| q.w * q.h).sum::<i32>(), 35);
+    }
+
+    #[test]
+    fn reject_exact_refuses_identical_rects() {
+        let mut tree = QuadTree::new(square(100), 1).with_duplicate_policy(DuplicatePolicy::RejectExact);
+
+        assert!(tree.insert(pt(10, 10)));
+        assert!(!tree.insert(pt(10, 10)));
+        assert!(!tree.insert_with_id(4, pt(10, 10)));
+        assert!(tree.insert_rect(Rect { x: 10, y: 10, w: 5, h: 5 }));
+        assert!(!tree.insert_rect(Rect { x: 10, y: 10, w: 5, h: 5 }));
+        assert_eq!(tree.len(), 2);
+
+        let mut allow = QuadTree::new(square(100), 1);
+        assert!(allow.insert(pt(10, 10)) && allow.insert(pt(10, 10)));
+    }
+
+    #[test]
+    fn convex_hull_of_square_with_interior_points() {
+        let mut tree = QuadTree::new(square(100), 2);
+        for p in [pt(10, 10), pt(90, 10), pt(90, 90), pt(10, 90), pt(50, 50), pt(30, 60), pt(50, 10)] {
+            tree.insert(p);
+        }
+
+        assert_eq!(tree.convex_hull(), vec![(10, 10), (90, 10), (90, 90), (10, 90)]);
+        assert_eq!(QuadTree::from_points(square(100), 2, &[pt(1, 1), pt(1, 1)]).convex_hull(), vec![(1, 1)]);
+    }
+
+    #[test]
+    fn query_cell_radius_covers_whole_tiles() {
+        let mut tree = QuadTree::new(square(100), 2).with_grid_cell_size(10);
+        for p in [pt(10, 10), pt(39, 39), pt(40, 20), pt(9, 20), pt(25, 25)] {
+            tree.insert(p);
+        }
+
+        let mut found = tree.query_cell_radius(2, 2, 1);
+        found.sort_by_key(|p| (p.x, p.y));
+        assert_eq!(found, vec![pt(10, 10), pt(25, 25), pt(39, 39)]);
+        assert_eq!(tree.query_cell_radius(2, 2, 0), vec![pt(25, 25)]);
+    }
+
+    #[test]
+    fn prune_empty_drops_only_empty_subtrees() {
+        let mut tree = QuadTree::new(square(100), 1);
+        for p in [pt(10, 10), pt(40, 10), pt(60, 60), pt(90, 90)] {
+            tree.insert(p);
+        }
+        tree.remove(&pt(10, 10));
+        tree.remove(&pt(40, 10));
+
+        let before = tree.node_count();
+        tree.prune_empty();
+        let [nw, _, _, se] = tree.children().unwrap();
+        assert!(nw.children().is_none());
+        assert!(se.children().is_some());
+        assert_eq!(tree.node_count(), before - 4);
+        assert_eq!(tree.len(), 2);
+    }
+
+    #[test]
+    fn generate_points_is_deterministic_and_in_bounds() {
+        let boundary = Rect { x: -50, y: 20, w: 30, h: 40 };
+        let points = generate_points(&boundary, 200, 42);
+
+        assert_eq!(points.len(), 200);
+        assert_eq!(points, generate_points(&boundary, 200, 42));
+        assert_ne!(points, generate_points(&boundary, 200, 43));
+        assert!(points.iter().all(|p| boundary.contains(p) && p.w == 1 && p.h == 1));
+    }
+
+    #[test]
+    fn morton_order_interleaves_coordinates() {
+        assert_eq!(morton(0b11, 0b00), 0b0101);
+        assert_eq!(morton(0b00, 0b11), 0b1010);
+
+        let tree = QuadTree::from_points(Rect { x: 10, y: 10, w: 4, h: 4 }, 4, &[pt(11, 11), pt(10, 11), pt(11, 10), pt(10, 10)]);
+        assert_eq!(tree.query_morton_ordered(tree.boundary()), vec![pt(10, 10), pt(11, 10), pt(10, 11), pt(11, 11)]);
+    }
+
+    #[test]
+    fn children_are_exposed_in_quadrant_order() {
+        let mut tree = QuadTree::new(square(100), 1);
+        tree.insert(pt(10, 10));
+        assert!(tree.children().is_none());
+
+        tree.insert(pt(60, 60));
+        let children = tree.children().unwrap();
+        let boundaries: Vec<Rect> = children.iter().map(|c| c.boundary().clone()).collect();
+        assert_eq!(boundaries, square(100).quadrants().to_vec());
+        assert_eq!(children.iter().map(|c| c.len()).collect::<Vec<_>>(), vec![1, 0, 0, 1]);
+    }
+
+    #[test]
+    fn points_accessor_returns_only_own_items() {
+        let mut tree = QuadTree::new(square(100), 1);
+        tree.insert(pt(10, 10));
+        assert_eq!(tree.points(), &[pt(10, 10)]);
+
+        tree.insert(pt(60, 60));
+        tree.insert_rect(Rect { x: 40, y: 40, w: 20, h: 20 });
+        assert_eq!(tree.points(), &[Rect { x: 40, y: 40, w: 20, h: 20 }]);
+        assert_eq!(tree.children().unwrap()[0].points(), &[pt(10, 10)]);
+
+        for p in generate_points(&square(100), 40, 23) {
+            tree.insert(p);
+        }
+        fn own_items(tree: &QuadTree) -> usize {
+            let below = tree.children().map_or(0, |children| children.iter().map(|child| own_items(child)).sum());
+            tree.points().len() + below
+        }
+        assert_eq!(own_items(&tree), tree.len());
+    }
+
+    #[test]
+    fn query_visit_sees_query_results_without_collecting() {
+        let tree = QuadTree::from_points(square(256), 3, &generate_points(&square(256), 300, 18));
+        let range = Rect { x: 40, y: 40, w: 90, h: 60 };
+
+        let mut visited = Vec::new();
+        tree.query_visit(&range, |p| visited.push(p.clone()));
+        visited.sort_by_key(|p| (p.x, p.y));
+        assert_eq!(visited, tree.query_ordered(&range));
+        assert_eq!(tree.count_in_range(&range), visited.len());
+    }
+
+    #[test]
+    #[cfg(feature = "saturating")]
+    fn spans_past_i32_max_saturate() {
+        let edge = Rect { x: i32::MAX - 10, y: 0, w: 100, h: 100 };
+        assert!(edge.contains_xy(i32::MAX, 5));
+        assert!(edge.touches(&Rect { x: i32::MAX - 1, y: 0, w: 50, h: 1 }));
+
+        let mut tree = QuadTree::new(edge.clone(), 1);
+        for x in [i32::MAX - 10, i32::MAX - 2, i32::MAX] {
+            assert!(tree.insert(pt(x, 5)));
+        }
+        assert_eq!(tree.query(&edge).len(), 3);
+        assert_eq!(tree.clamp_to_boundary(&edge), Rect { x: i32::MAX - 10, y: 0, w: 10, h: 100 });
+        assert_eq!(tree.quadrant_counts(i32::MAX - 5, 50), [1, 2, 0, 0]);
+        assert_eq!(tree.query_annulus(i32::MAX - 10, 5, 5, 20).len(), 2);
+        assert_eq!(tree.query_circle(i32::MAX, 5, 1).len(), 1);
+
+        let mut discs = QuadTree::new(edge.clone(), 1);
+        discs.insert_disc(i32::MAX - 5, 50, 3);
+        discs.insert_disc(i32::MAX, 60, 3);
+        assert_eq!(discs.query_discs(&Rect { x: i32::MAX - 1, y: 55, w: 10, h: 10 }).len(), 1);
+
+        let mut median = QuadTree::new(edge.clone(), 1).with_split_strategy(SplitStrategy::Median);
+        for x in [i32::MAX - 10, i32::MAX - 2, i32::MAX] {
+            assert!(median.insert(pt(x, 5)));
+        }
+        assert_eq!(median.query(&edge).len(), 3);
+    }
+
+    #[test]
+    fn geojson_lists_points_as_features() {
+        let tree = QuadTree::from_points(square(100), 2, &[pt(3, 4)]);
+
+        assert_eq!(
+            tree.query_geojson(&square(100)),
+            r#"{"type":"FeatureCollection","features":[{"type":"Feature","geometry":{"type":"Point","coordinates":[3,4]},"properties":{}}]}"#
+        );
+        assert_eq!(tree.query_geojson(&square(1)), r#"{"type":"FeatureCollection","features":[]}"#);
+    }
+
+    #[test]
+    fn nearest_empty_cell_prefers_closest_leaf() {
+        let mut tree = QuadTree::new(square(100), 1);
+        assert_eq!(tree.nearest_empty_cell(10, 10), Some(square(100)));
+
+        for p in [pt(10, 10), pt(60, 10), pt(10, 60)] {
+            tree.insert(p);
+        }
+        let se = Rect { x: 50, y: 50, w: 50, h: 50 };
+        assert_eq!(tree.nearest_empty_cell(80, 80), Some(se.clone()));
+        assert_eq!(tree.nearest_empty_cell(10, 10), Some(se));
+
+        tree.insert(pt(60, 60));
+        assert_eq!(tree.nearest_empty_cell(10, 10), None);
+    }
+
+    #[test]
+    fn equality_compares_structure_and_points() {
+        let points = generate_points(&square(100), 30, 19);
+        let reversed: Vec<Rect> = points.iter().rev().cloned().collect();
+        let a = QuadTree::from_points(square(100), 40, &points);
+
+        assert!(a == QuadTree::from_points(square(100), 40, &reversed));
+        assert!(a == a.clone());
+        assert!(a != QuadTree::from_points(square(100), 2, &points));
+        assert!(a != QuadTree::from_points(square(100), 40, &points[1..]));
+        assert!(a != QuadTree::from_points(square(200), 40, &points));
+    }
+
+    #[test]
+    fn unchecked_rebuild_matches_checked_insertion() {
+        let quarter = Rect { x: 0, y: 0, w: 128, h: 128 };
+        let points = generate_points(&quarter, 300, 21);
+        let mut tree = QuadTree::from_points(square(256), 4, &points);
+        tree.shrink_root();
+
+        assert_eq!(tree.boundary(), &quarter);
+        assert!(tree == QuadTree::from_points(quarter, 4, &points));
+    }
+
+    #[test]
+    fn max_points_skips_removed_items_when_evicting() {
+        let mut tree = QuadTree::new(square(100), 2).with_max_points(3);
+        for p in [pt(10, 10), pt(20, 20), pt(30, 30)] {
+            tree.insert(p);
+        }
+        tree.remove(&pt(10, 10));
+        tree.insert(pt(40, 40));
+        assert_eq!(tree.len(), 3);
+
+        tree.insert(pt(50, 50));
+        assert_eq!(tree.query_ordered(&square(100)), vec![pt(30, 30), pt(40, 40), pt(50, 50)]);
+    }
+
+    #[test]
+    fn max_points_evicts_duplicated_rects_whole() {
+        let mut tree = QuadTree::new(square(100), 1)
+            .with_straddle_policy(StraddlePolicy::Duplicate)
+            .with_max_points(4);
+        tree.insert_rect(Rect { x: 40, y: 40, w: 20, h: 20 });
+        tree.insert(pt(10, 10));
+        assert_eq!(tree.len(), 1);
+        assert!(tree.query_overlaps(&square(100)) == vec![pt(10, 10)]);
+    }
+
+    #[test]
+    fn max_points_applies_to_loaded_trees() {
+        let points = generate_points(&square(100), 20, 22);
+        let mut tree = QuadTree::from_points(square(100), 2, &points).with_max_points(20);
+        tree.insert(pt(50, 50));
+
+        assert_eq!(tree.len(), 20);
+        assert!(!tree.query_indexed(&square(100)).iter().any(|(i, _)| *i == 0));
+    }
+
+    #[test]
+    fn move_point_keeps_insertion_order() {
+        let mut tree = QuadTree::new(square(100), 1).with_max_points(3);
+        tree.insert(pt(10, 10));
+        let since = tree.insertion_seq();
+        tree.insert(pt(20, 20));
+        tree.insert(pt(30, 30));
+
+        assert!(tree.move_point(&pt(10, 10), pt(90, 90)));
+        let mut indexed = tree.query_indexed(&square(100));
+        indexed.sort_by_key(|(i, _)| *i);
+        assert_eq!(indexed, vec![(0, pt(90, 90)), (1, pt(20, 20)), (2, pt(30, 30))]);
+        assert!(!tree.query_recent(&square(100), since).contains(&pt(90, 90)));
+
+        tree.insert(pt(40, 40));
+        assert!(!tree.contains_point(&pt(90, 90)));
+        assert_eq!(tree.len(), 3);
+    }
+
+    #[test]
+    fn remove_drops_every_duplicate_copy() {
+        let big = Rect { x: 40, y: 40, w: 20, h: 20 };
+        let mut tree = QuadTree::new(square(100), 1).with_straddle_policy(StraddlePolicy::Duplicate);
+        tree.insert_rect(big.clone());
+        tree.insert_rect(Rect { x: 5, y: 5, w: 5, h: 5 });
+        tree.insert_rect(Rect { x: 70, y: 10, w: 5, h: 5 });
+        assert_eq!(tree.len(), 6);
+
+        assert!(tree.remove(&big));
+        assert_eq!(tree.len(), 2);
+        assert!(!tree.query_overlaps(&square(100)).contains(&big));
+        tree.debug_check_invariants();
+    }
+
+    #[test]
+    fn query_skips_duplicate_copies_outside_range() {
+        let big = Rect { x: 40, y: 40, w: 20, h: 20 };
+        let mut tree = QuadTree::new(square(100), 1).with_straddle_policy(StraddlePolicy::Duplicate);
+        tree.insert_rect(big.clone());
+        tree.insert_rect(Rect { x: 5, y: 5, w: 5, h: 5 });
+        tree.insert_rect(Rect { x: 70, y: 10, w: 5, h: 5 });
+
+        let range = Rect { x: 50, y: 0, w: 50, h: 50 };
+        assert_eq!(tree.query(&range), vec![Rect { x: 70, y: 10, w: 5, h: 5 }]);
+        assert_eq!(tree.query_many(&[range]), vec![vec![Rect { x: 70, y: 10, w: 5, h: 5 }]]);
+    }
+
+    #[test]
+    fn move_point_places_sized_rects_by_area() {
+        let rect = Rect { x: 30, y: 30, w: 40, h: 40 };
+        let moved = Rect { x: 35, y: 35, w: 40, h: 40 };
+        let mut tree = QuadTree::new(square(100), 1);
+        tree.insert_rect(rect.clone());
+        for p in [pt(10, 10), pt(90, 10), pt(10, 90), pt(90, 90)] {
+            tree.insert(p);
+        }
+
+        assert!(tree.move_point(&rect, moved.clone()));
+        assert_eq!(tree.query_overlaps(&Rect { x: 60, y: 60, w: 1, h: 1 }), vec![moved.clone()]);
+        assert!(!tree.move_point(&moved, Rect { x: 70, y: 70, w: 40, h: 40 }));
+        tree.debug_check_invariants();
+    }
+
+    #[test]
+    fn snap_to_grid_applies_to_every_insert() {
+        let mut tree = QuadTree::new(square(100), 4).with_snap_to_grid(10, true).with_recording();
+
+        assert!(tree.insert(pt(10, 10)));
+        assert!(!tree.insert_with_id(1, pt(11, 11)));
+        assert!(!tree.insert_disc(9, 12, 3));
+        assert!(tree.insert_with_id(2, pt(24, 26)));
+        assert!(tree.insert_disc(41, 39, 3));
+
+        assert_eq!(tree.query_ids(&square(100)), vec![(2, pt(20, 30))]);
+        assert_eq!(tree.query_ordered(&square(100)), vec![pt(10, 10), pt(20, 30), pt(40, 40)]);
+        assert!(tree.replay(tree.ops()) == tree);
+    }
+}
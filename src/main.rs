@@ -1,194 +1,90 @@
-use raylib::prelude::*;
-
-
-#[derive(Clone, Debug)]
-struct Rect {
-    x: i32,
-    y: i32,
-    w: i32,
-    h: i32,
-}
+use std::time::{Duration, Instant};
 
-impl Rect {
-    fn contains(&self, point: &Rect) -> bool {
-        point.x >= self.x && point.x <= self.x + self.w &&
-        point.y >= self.y && point.y <= self.y + self.h
-    }
-
-    fn intersects(&self, range: &Rect) -> bool {
-        self.x < range.x + range.w &&
-        self.x + self.w > range.x &&
-        self.y < range.y + range.h &&
-        self.y + self.h > range.y
-    }
-}
+use quadtree::{generate_points, DrawStyle, Point, QuadTree, Rect};
+use raylib::prelude::*;
 
-struct QuadTree {
-    boundary: Rect,
-    capacity: usize,
-    points: Vec<Rect>,
-    north_west: Option<Box<QuadTree>>,
-    north_east: Option<Box<QuadTree>>,
-    south_west: Option<Box<QuadTree>>,
-    south_east: Option<Box<QuadTree>>,
+const MIN_INSERT_SPACING: i32 = 3;
+const GRID_CELL_SIZE: i32 = 32;
+const PRUNING_SAMPLES: usize = 1000;
+const RADIUS_STEP: f32 = 4.0;
+const PANEL_WIDTH: i32 = 170;
+const STRESS_POINTS: usize = 500;
+
+struct GridHash {
+    cell_size: i32,
+    cols: i32,
+    rows: i32,
+    cells: Vec<Vec<Rect>>,
 }
 
-impl QuadTree {
-    fn new(boundary: Rect, capacity: usize) -> Self {
-        QuadTree {
-            boundary,
-            capacity,
-            points: Vec::new(),
-            north_west: None,
-            north_east: None,
-            south_west: None,
-            south_east: None,
-        }
-    }
-
-    fn insert(&mut self, point: Rect)  -> bool {
-        if !self.boundary.contains(&point) {
-            return false;
-        }
-
-        if self.points.len() < self.capacity && self.north_west.is_none() {
-            self.points.push(point);
-            return true;
-        }
-
-        if self.north_west.is_none() {
-            self.subdivide();
-        }
-
-        match self.north_west {
-            Some(ref mut tree) => {
-                if tree.insert(point.clone()) { return true; }
-            }
-            None => {}
-        }
+impl GridHash {
+    fn new(boundary: &Rect, cell_size: i32) -> Self {
+        let cols = boundary.w / cell_size + 1;
+        let rows = boundary.h / cell_size + 1;
 
-        match self.north_east {
-            Some(ref mut tree) => {
-                if tree.insert(point.clone()) { return true; }
-            }
-            None => {}
+        GridHash {
+            cell_size,
+            cols,
+            rows,
+            cells: vec![Vec::new(); (cols * rows) as usize],
         }
-
-        match self.south_west {
-            Some(ref mut tree) => {
-                if tree.insert(point.clone()) { return true; }
-            }
-            None => {}
-        }
-
-        match self.south_east {
-            Some(ref mut tree) => {
-                if tree.insert(point.clone()) { return true; }
-            }
-            None => {}
-        }
-
-        false
     }
 
-    fn subdivide(&mut self) {
-        let x = self.boundary.x;
-        let y = self.boundary.y;
-        let w = self.boundary.w;
-        let h = self.boundary.h;
-
-        let nw = Rect { x, y, w: w / 2, h: h / 2 };
-        let ne = Rect { x: x + w / 2, y, w: w / 2, h: h / 2 };
-        let sw = Rect { x, y: y + h / 2, w: w / 2, h: h / 2 };
-        let se = Rect { x: x + w / 2, y: y + h / 2, w: w / 2, h: h / 2 };
-
-        self.north_west = Some(Box::new(QuadTree::new(nw, self.capacity)));
-        self.north_east = Some(Box::new(QuadTree::new(ne, self.capacity)));
-        self.south_west = Some(Box::new(QuadTree::new(sw, self.capacity)));
-        self.south_east = Some(Box::new(QuadTree::new(se, self.capacity)));
+    fn insert(&mut self, point: Rect) {
+        let col = (point.x / self.cell_size).clamp(0, self.cols - 1);
+        let row = (point.y / self.cell_size).clamp(0, self.rows - 1);
+        self.cells[(row * self.cols + col) as usize].push(point);
     }
 
-    fn query(&self, range: Rect) -> Option<Vec<Rect>> {
+    fn query(&self, range: &Rect) -> Vec<Rect> {
         let mut points = Vec::new();
 
-        if !self.boundary.intersects(&range) {
-            return None;
-        }
-
-        for point in &self.points {
-            if range.contains(point) {
-                points.push(point.clone());
-            }
-        }
-
-        if self.north_west.is_none() {
-            return Some(points);
-        }
-
-        match self.north_west {
-            Some(ref tree) => {
-                if let Some(mut p) = tree.query(range.clone()) {
-                    points.append(&mut p);
-                }
-            }
-            None => {}
-        }
-
-        match self.north_east {
-            Some(ref tree) => {
-                if let Some(mut p) = tree.query(range.clone()) {
-                    points.append(&mut p);
-                }
-            }
-            None => {}
-        }
-
-        match self.south_west {
-            Some(ref tree) => {
-                if let Some(mut p) = tree.query(range.clone()) {
-                    points.append(&mut p);
-                }
-            }
-            None => {}
-        }
-
-        match self.south_east {
-            Some(ref tree) => {
-                if let Some(mut p) = tree.query(range.clone()) {
-                    points.append(&mut p);
+        let min_col = (range.x / self.cell_size).clamp(0, self.cols - 1);
+        let max_col = ((range.x + range.w) / self.cell_size).clamp(0, self.cols - 1);
+        let min_row = (range.y / self.cell_size).clamp(0, self.rows - 1);
+        let max_row = ((range.y + range.h) / self.cell_size).clamp(0, self.rows - 1);
+
+        for row in min_row..=max_row {
+            for col in min_col..=max_col {
+                for point in &self.cells[(row * self.cols + col) as usize] {
+                    if range.contains(point) {
+                        points.push(point.clone());
+                    }
                 }
             }
-            None => {}
         }
 
-        Some(points)
+        points
     }
+}
 
-    fn draw(&self, d: &mut RaylibDrawHandle) {
-        d.draw_rectangle_lines(self.boundary.x, self.boundary.y, self.boundary.w, self.boundary.h, Color::BLACK);
-
-        if self.north_west.is_some() {
-            self.north_west.as_ref().unwrap().draw(d);
-        }
-
-        if self.north_east.is_some() {
-            self.north_east.as_ref().unwrap().draw(d);
-        }
+/// Points from `from` (exclusive) to `to` (inclusive), spaced about `step`
+/// apart along the longer axis. Empty when the two are closer than `step`.
+fn line_points(from: (i32, i32), to: (i32, i32), step: i32) -> Vec<(i32, i32)> {
+    let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+    let steps = dx.abs().max(dy.abs()) / step;
 
-        if self.south_west.is_some() {
-            self.south_west.as_ref().unwrap().draw(d);
-        }
+    (1..=steps)
+        .map(|i| (from.0 + dx * i / steps, from.1 + dy * i / steps))
+        .collect()
+}
 
-        if self.south_east.is_some() {
-            self.south_east.as_ref().unwrap().draw(d);
+/// A random direction for moving mode, one snap cell per frame on each
+/// axis. Never `(0, 0)`, so every point keeps moving.
+fn random_velocity(rl: &RaylibHandle) -> (i32, i32) {
+    loop {
+        let (vx, vy) = (rl.get_random_value::<i32>(-1, 1), rl.get_random_value::<i32>(-1, 1));
+        if (vx, vy) != (0, 0) {
+            return (vx * MIN_INSERT_SPACING, vy * MIN_INSERT_SPACING);
         }
     }
 }
 
 fn main() {
     let mut rects: Vec<Rect> = Vec::new();
-    let mut quadtree = QuadTree::new(Rect { x: 0, y: 0, w: 800, h: 450 }, 4);
-    let mut selected_rects: Vec<Rect> = Vec::new();
+    let mut velocities: Vec<(i32, i32)> = Vec::new();
+    let boundary = Rect { x: 0, y: 0, w: 800, h: 450 };
+    let mut quadtree = QuadTree::new(boundary.clone(), 4).with_snap_to_grid(MIN_INSERT_SPACING, true);
 
     let (mut rl, thread) = raylib::init()
     .size(800, 450)
@@ -199,22 +95,52 @@ fn main() {
 
     let mut is_mouse_down = false;
     let mut selection_rect = Rect { x: 0, y: 0, w: 0, h: 0 };
+    let mut draw_query_path = false;
+    let mut moving = false;
+    let mut compare_grid = false;
+    let mut leaves_only = false;
+    let mut circle_select = false;
+    let mut circle_radius = 40;
+    let mut circle_rects: Vec<Rect> = Vec::new();
+    let mut lasso: Vec<(i32, i32)> = Vec::new();
+    let mut lasso_closed = false;
+    let mut lasso_rects: Vec<Rect> = Vec::new();
+    let mut comparison = String::new();
+    let mut visited_nodes: Vec<Rect> = Vec::new();
+    let mut last_insert: Option<(i32, i32)> = None;
+    let mut selected_rects: Vec<Rect> = Vec::new();
+    let mut quadtree_time = Duration::ZERO;
+    let mut last_selection = Rect { x: 0, y: 0, w: 0, h: 0 };
+    let mut dirty = true;
+    let mut explanation: Vec<String> = Vec::new();
+    let mut stress_seed = 0;
+    let style = DrawStyle::default();
 
     while !rl.window_should_close() {
-        if rl.is_mouse_button_down(
-            MouseButton::MOUSE_BUTTON_LEFT
-        ) {
-            let mouse_pos = rl.get_mouse_position();
-            let rect = Rect {
-                x: mouse_pos.x as i32,
-                y: mouse_pos.y as i32,
-                w: 1,
-                h: 1,
+        if rl.is_mouse_button_pressed(MouseButton::MOUSE_BUTTON_LEFT) {
+            last_insert = None;
+        }
+
+        let mouse_pos = rl.get_mouse_position();
+        let mouse = (mouse_pos.x as i32, mouse_pos.y as i32);
+
+        if rl.is_mouse_button_down(MouseButton::MOUSE_BUTTON_LEFT) {
+            let stroke = match last_insert {
+                Some(last) => line_points(last, mouse, MIN_INSERT_SPACING),
+                None => vec![mouse],
             };
-            rects.push(rect.clone());
-            quadtree.insert(rect);
-            selection_rect.x = mouse_pos.x as i32;
-            selection_rect.y = mouse_pos.y as i32;
+
+            for (x, y) in stroke {
+                last_insert = Some((x, y));
+                let point = Point { x, y };
+                if quadtree.insert_or_log(point) {
+                    dirty = true;
+                    rects.push(quadtree.snapped(&Rect::from(point)));
+                    velocities.push(random_velocity(&rl));
+                }
+                selection_rect.x = x;
+                selection_rect.y = y;
+            }
         }
 
         if rl.is_mouse_button_down(MouseButton::MOUSE_BUTTON_RIGHT) {
@@ -241,26 +167,192 @@ fn main() {
             selection_rect.h = mouse_pos.y as i32 - selection_rect.y;
         }
 
-        let points_in_range = quadtree.query(selection_rect.clone());
-        match points_in_range {
-            Some(points) => {
-                selected_rects = points;
+        let selection = selection_rect.normalized();
+        if selection != last_selection {
+            last_selection = selection.clone();
+            dirty = true;
+        }
+
+        if rl.is_key_pressed(KeyboardKey::KEY_M) {
+            moving = !moving;
+        }
+
+        if moving {
+            for (rect, velocity) in rects.iter_mut().zip(velocities.iter_mut()) {
+                let mut moved = rect.clone();
+                moved.x += velocity.0;
+                moved.y += velocity.1;
+
+                if moved.x < boundary.x || moved.x >= boundary.x + boundary.w {
+                    velocity.0 = -velocity.0;
+                    moved.x = moved.x.clamp(boundary.x, boundary.x + boundary.w - 1);
+                }
+                if moved.y < boundary.y || moved.y >= boundary.y + boundary.h {
+                    velocity.1 = -velocity.1;
+                    moved.y = moved.y.clamp(boundary.y, boundary.y + boundary.h - 1);
+                }
+
+                if moved != *rect && quadtree.move_point(rect, moved.clone()) {
+                    *rect = quadtree.snapped(&moved);
+                    dirty = true;
+                }
             }
-            None => {
-                selected_rects.clear();
+        }
+
+        if rl.is_key_pressed(KeyboardKey::KEY_T) {
+            draw_query_path = !draw_query_path;
+        }
+
+        if dirty {
+            let started = Instant::now();
+            selected_rects = quadtree.query(&selection);
+            quadtree_time = started.elapsed();
+
+            let (found, visited) = quadtree.query_with_trace(&selection);
+            let total = quadtree.node_count();
+            explanation = vec![
+                format!("points found: {}", found.len()),
+                format!("nodes visited: {} / {}", visited.len(), total),
+                format!("pruned: {:.1}%", (1.0 - visited.len() as f32 / total as f32) * 100.0),
+            ];
+            visited_nodes = visited;
+            dirty = false;
+        }
+
+        if rl.is_key_pressed(KeyboardKey::KEY_P) {
+            let ranges: Vec<Rect> = (0..PRUNING_SAMPLES)
+                .map(|_| Rect {
+                    x: rl.get_random_value::<i32>(0, boundary.w),
+                    y: rl.get_random_value::<i32>(0, boundary.h),
+                    w: rl.get_random_value::<i32>(1, boundary.w / 4),
+                    h: rl.get_random_value::<i32>(1, boundary.h / 4),
+                })
+                .collect();
+
+            println!("visited {:.1}% of nodes on average", quadtree.pruning_ratio(&ranges) * 100.0);
+        }
+
+        if rl.is_key_pressed(KeyboardKey::KEY_S) {
+            for point in generate_points(&boundary, STRESS_POINTS, stress_seed) {
+                let point = quadtree.snapped(&point);
+                if quadtree.insert(point.clone()) {
+                    rects.push(point);
+                    velocities.push(random_velocity(&rl));
+                }
             }
+            stress_seed += 1;
+            dirty = true;
         }
 
+        if rl.is_key_pressed(KeyboardKey::KEY_C) {
+            circle_select = !circle_select;
+        }
+
+        if circle_select {
+            let wheel = rl.get_mouse_wheel_move();
+            circle_radius = (circle_radius + (wheel * RADIUS_STEP) as i32).max(1);
+
+            let mouse_pos = rl.get_mouse_position();
+            circle_rects = quadtree.query_circle(mouse_pos.x as i32, mouse_pos.y as i32, circle_radius);
+        } else {
+            circle_rects.clear();
+        }
+
+        if rl.is_mouse_button_pressed(MouseButton::MOUSE_BUTTON_MIDDLE) {
+            if lasso_closed {
+                lasso.clear();
+                lasso_closed = false;
+            }
+            let mouse_pos = rl.get_mouse_position();
+            lasso.push((mouse_pos.x as i32, mouse_pos.y as i32));
+        }
+
+        if rl.is_key_pressed(KeyboardKey::KEY_ENTER) && lasso.len() >= 3 {
+            lasso_closed = true;
+        }
+
+        if rl.is_key_pressed(KeyboardKey::KEY_BACKSPACE) {
+            lasso.clear();
+            lasso_closed = false;
+        }
+
+        if lasso_closed {
+            lasso_rects = quadtree.query_polygon(&lasso);
+        } else {
+            lasso_rects.clear();
+        }
+
+        if rl.is_key_pressed(KeyboardKey::KEY_L) {
+            leaves_only = !leaves_only;
+        }
+
+        if rl.is_key_pressed(KeyboardKey::KEY_G) {
+            compare_grid = !compare_grid;
+        }
+
+        if compare_grid {
+            let mut grid = GridHash::new(&boundary, GRID_CELL_SIZE);
+            for rect in &rects {
+                grid.insert(rect.clone());
+            }
+
+            let started = Instant::now();
+            let grid_points = grid.query(&selection);
+            let grid_time = started.elapsed();
+
+            comparison = format!(
+                "quadtree: {} points in {} µs / grid: {} points in {} µs",
+                selected_rects.len(), quadtree_time.as_micros(),
+                grid_points.len(), grid_time.as_micros()
+            );
+        }
+
+        let mouse_pos = rl.get_mouse_position();
+        let hovered_leaf = quadtree.leaf_boundary(mouse_pos.x as i32, mouse_pos.y as i32).cloned();
+        let hovered_len = quadtree.leaf_len(mouse_pos.x as i32, mouse_pos.y as i32);
+
         let mut d = rl.begin_drawing(&thread);
 
         d.clear_background(Color::WHITE);
-        for rect in &rects {
-            d.draw_rectangle(rect.x, rect.y, rect.w, rect.h, Color::RED);
+        if draw_query_path {
+            for node in &visited_nodes {
+                d.draw_rectangle(node.x, node.y, node.w, node.h, Color::new(255, 200, 0, 60));
+            }
+        }
+        quadtree.draw_selection(&mut d, &style, &selection);
+        for rect in &circle_rects {
+            d.draw_rectangle(rect.x, rect.y, rect.w, rect.h, Color::PURPLE);
+        }
+        for rect in &lasso_rects {
+            d.draw_rectangle(rect.x, rect.y, rect.w, rect.h, Color::ORANGE);
+        }
+        for edge in lasso.windows(2) {
+            d.draw_line(edge[0].0, edge[0].1, edge[1].0, edge[1].1, Color::ORANGE);
+        }
+        if let (true, Some(first), Some(last)) = (lasso_closed, lasso.first(), lasso.last()) {
+            d.draw_line(last.0, last.1, first.0, first.1, Color::ORANGE);
+        }
+        if circle_select {
+            d.draw_circle_lines(mouse_pos.x as i32, mouse_pos.y as i32, circle_radius as f32, Color::PURPLE);
+        }
+        if leaves_only {
+            quadtree.draw_leaves_only(&mut d, &style);
+        } else {
+            quadtree.draw(&mut d, &style);
+        }
+        if compare_grid {
+            d.draw_text(&comparison, 10, 430, 10, Color::BLACK);
+        }
+        if selection.w > 0 && selection.h > 0 {
+            let panel_x = boundary.w - PANEL_WIDTH - 10;
+            d.draw_rectangle(panel_x, 10, PANEL_WIDTH, 14 * explanation.len() as i32 + 8, Color::new(255, 255, 255, 220));
+            for (i, line) in explanation.iter().enumerate() {
+                d.draw_text(line, panel_x + 6, 14 + 14 * i as i32, 10, Color::BLACK);
+            }
         }
-        for rect in &selected_rects {
-            d.draw_rectangle(rect.x, rect.y, rect.w, rect.h, Color::BLUE);
+        if let Some(leaf) = hovered_leaf {
+            d.draw_rectangle(leaf.x, leaf.y, leaf.w, leaf.h, Color::new(0, 120, 255, 50));
+            d.draw_text(&hovered_len.to_string(), leaf.x + 2, leaf.y + 2, 10, Color::DARKBLUE);
         }
-        d.draw_rectangle(selection_rect.x, selection_rect.y, selection_rect.w, selection_rect.h, Color::new(0, 255, 0, 100));
-        quadtree.draw(&mut d);
     }
 }
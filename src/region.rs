@@ -0,0 +1,246 @@
+use crate::{QuadTree, Rect};
+
+pub trait Region {
+    fn intersects_aabb(&self, aabb: &Rect) -> bool;
+    fn contains_point(&self, x: i32, y: i32) -> bool;
+}
+
+impl Region for Rect {
+    fn intersects_aabb(&self, aabb: &Rect) -> bool {
+        self.touches(aabb)
+    }
+
+    fn contains_point(&self, x: i32, y: i32) -> bool {
+        self.contains_xy(x, y)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Circle {
+    pub x: i32,
+    pub y: i32,
+    pub radius: i32,
+}
+
+impl Region for Circle {
+    fn intersects_aabb(&self, aabb: &Rect) -> bool {
+        let center = Rect { x: self.x, y: self.y, w: 0, h: 0 };
+        aabb.gap_sq(&center) <= self.radius as i64 * self.radius as i64
+    }
+
+    fn contains_point(&self, x: i32, y: i32) -> bool {
+        let dx = x as i64 - self.x as i64;
+        let dy = y as i64 - self.y as i64;
+        dx * dx + dy * dy <= self.radius as i64 * self.radius as i64
+    }
+}
+
+struct Annulus {
+    inner: Circle,
+    outer: Circle,
+}
+
+impl Region for Annulus {
+    fn intersects_aabb(&self, aabb: &Rect) -> bool {
+        let (cx, cy) = (self.outer.x as i64, self.outer.y as i64);
+        let far_x = (aabb.x as i64 - cx).abs().max((aabb.right() as i64 - cx).abs());
+        let far_y = (aabb.y as i64 - cy).abs().max((aabb.bottom() as i64 - cy).abs());
+        let inside_inner = far_x * far_x + far_y * far_y < self.inner.radius as i64 * self.inner.radius as i64;
+
+        self.outer.intersects_aabb(aabb) && !inside_inner
+    }
+
+    fn contains_point(&self, x: i32, y: i32) -> bool {
+        let dx = x as i64 - self.outer.x as i64;
+        let dy = y as i64 - self.outer.y as i64;
+        let dist_sq = dx * dx + dy * dy;
+
+        dist_sq >= self.inner.radius as i64 * self.inner.radius as i64 && self.outer.contains_point(x, y)
+    }
+}
+
+struct Polygon<'a> {
+    vertices: &'a [(i32, i32)],
+    bounds: Rect,
+}
+
+impl<'a> Polygon<'a> {
+    fn new(vertices: &'a [(i32, i32)]) -> Self {
+        let min_x = vertices.iter().map(|v| v.0).min().unwrap_or(0);
+        let max_x = vertices.iter().map(|v| v.0).max().unwrap_or(0);
+        let min_y = vertices.iter().map(|v| v.1).min().unwrap_or(0);
+        let max_y = vertices.iter().map(|v| v.1).max().unwrap_or(0);
+
+        Polygon { vertices, bounds: Rect { x: min_x, y: min_y, w: max_x - min_x, h: max_y - min_y } }
+    }
+}
+
+impl Region for Polygon<'_> {
+    fn intersects_aabb(&self, aabb: &Rect) -> bool {
+        self.vertices.len() >= 3 && self.bounds.touches(aabb)
+    }
+
+    /// Even-odd ray casting, so concave outlines work.
+    fn contains_point(&self, x: i32, y: i32) -> bool {
+        let (x, y) = (x as f64, y as f64);
+        let mut inside = false;
+        let mut j = self.vertices.len() - 1;
+
+        for i in 0..self.vertices.len() {
+            let (xi, yi) = (self.vertices[i].0 as f64, self.vertices[i].1 as f64);
+            let (xj, yj) = (self.vertices[j].0 as f64, self.vertices[j].1 as f64);
+
+            if (yi > y) != (yj > y) && x < (xj - xi) * (y - yi) / (yj - yi) + xi {
+                inside = !inside;
+            }
+            j = i;
+        }
+
+        inside
+    }
+}
+
+impl QuadTree {
+    /// Points inside the polygon outlined by `vertices`, which may be
+    /// concave. Fewer than three vertices select nothing.
+    pub fn query_polygon(&self, vertices: &[(i32, i32)]) -> Vec<Rect> {
+        self.query_region(&Polygon::new(vertices))
+    }
+
+    pub fn query_circle(&self, cx: i32, cy: i32, radius: i32) -> Vec<Rect> {
+        self.query_region(&Circle { x: cx, y: cy, radius })
+    }
+
+    pub fn count_in_radius(&self, cx: i32, cy: i32, radius: i32) -> usize {
+        self.count_region(&Circle { x: cx, y: cy, radius })
+    }
+
+    fn count_region<R: Region>(&self, region: &R) -> usize {
+        if self.count == 0 || !region.intersects_aabb(&self.boundary) {
+            return 0;
+        }
+
+        let own = self.points.iter().filter(|p| region.contains_point(p.x, p.y)).count();
+        own + self.children_iter().map(|tree| tree.count_region(region)).sum::<usize>()
+    }
+
+    pub fn query_annulus(&self, cx: i32, cy: i32, inner: i32, outer: i32) -> Vec<Rect> {
+        self.query_region(&Annulus {
+            inner: Circle { x: cx, y: cy, radius: inner },
+            outer: Circle { x: cx, y: cy, radius: outer },
+        })
+    }
+
+    pub fn query_region<R: Region>(&self, region: &R) -> Vec<Rect> {
+        let mut points = Vec::new();
+        self.query_region_into(region, &mut points);
+        points
+    }
+
+    fn query_region_into<R: Region>(&self, region: &R, points: &mut Vec<Rect>) {
+        if self.count == 0 || !region.intersects_aabb(&self.boundary) {
+            return;
+        }
+
+        for point in &self.points {
+            if region.contains_point(point.x, point.y) {
+                points.push(point.clone());
+            }
+        }
+
+        for tree in self.children_iter() {
+            tree.query_region_into(region, points);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate_points;
+
+    fn square(side: i32) -> Rect {
+        Rect { x: 0, y: 0, w: side, h: side }
+    }
+
+    fn sample() -> (QuadTree, Vec<Rect>) {
+        let points = generate_points(&square(256), 500, 5);
+        (QuadTree::from_points(square(256), 2, &points), points)
+    }
+
+    fn within(p: &Rect, cx: i32, cy: i32, r: i32) -> bool {
+        (p.x - cx).pow(2) + (p.y - cy).pow(2) <= r * r
+    }
+
+    /// Everything left of a vertical line.
+    struct WestOf(i32);
+
+    impl Region for WestOf {
+        fn intersects_aabb(&self, aabb: &Rect) -> bool {
+            aabb.x <= self.0
+        }
+
+        fn contains_point(&self, x: i32, _y: i32) -> bool {
+            x <= self.0
+        }
+    }
+
+    #[test]
+    fn rect_region_matches_query() {
+        let (tree, _) = sample();
+        let range = Rect { x: 10, y: 20, w: 140, h: 100 };
+
+        let mut found = tree.query_region(&range);
+        found.sort_by_key(|p| (p.x, p.y));
+        assert_eq!(found, tree.query_ordered(&range));
+    }
+
+    #[test]
+    fn circle_and_custom_regions_match_brute_force() {
+        let (tree, points) = sample();
+
+        let circle = tree.query_region(&Circle { x: 100, y: 100, radius: 40 });
+        assert_eq!(circle.len(), points.iter().filter(|p| within(p, 100, 100, 40)).count());
+        assert_eq!(tree.query_circle(100, 100, 40).len(), circle.len());
+
+        let west = tree.query_region(&WestOf(30));
+        assert_eq!(west.len(), points.iter().filter(|p| p.x <= 30).count());
+    }
+
+    #[test]
+    fn annulus_matches_brute_force() {
+        let (tree, points) = sample();
+        let expected = points.iter()
+            .filter(|p| (900..=3600).contains(&((p.x - 128).pow(2) + (p.y - 128).pow(2))))
+            .count();
+
+        assert!(expected > 0);
+        assert_eq!(tree.query_annulus(128, 128, 30, 60).len(), expected);
+        assert!(tree.query_annulus(128, 128, 61, 60).is_empty());
+    }
+
+    #[test]
+    fn count_in_radius_matches_circle_query() {
+        let (tree, points) = sample();
+
+        for (cx, cy, r) in [(100, 100, 40), (0, 0, 10), (128, 128, 0), (300, 300, 10)] {
+            assert_eq!(tree.count_in_radius(cx, cy, r), tree.query_circle(cx, cy, r).len());
+            assert_eq!(tree.count_in_radius(cx, cy, r), points.iter().filter(|p| within(p, cx, cy, r)).count());
+        }
+    }
+
+    #[test]
+    fn polygon_query_handles_concave_outlines() {
+        let mut tree = QuadTree::new(square(100), 2);
+        for (x, y) in [(10, 10), (10, 80), (80, 80), (80, 10), (50, 50)] {
+            tree.insert(Rect { x, y, w: 1, h: 1 });
+        }
+
+        // A U shape open at the top, with (50, 50) in the notch.
+        let u = [(0, 0), (30, 0), (30, 60), (70, 60), (70, 0), (100, 0), (100, 100), (0, 100)];
+        let found = tree.query_polygon(&u);
+        assert_eq!(found.len(), 4);
+        assert!(!found.contains(&Rect { x: 50, y: 50, w: 1, h: 1 }));
+        assert!(tree.query_polygon(&[(0, 0), (100, 100)]).is_empty());
+    }
+}